@@ -0,0 +1,287 @@
+use std::{
+    error::Error,
+    fmt,
+    io::{self, prelude::*},
+};
+
+// Limits on what a client can make us read, so a broken or hostile one can't make the
+// server buffer without end.
+const MAX_LINE_LENGTH: u64 = 8 * 1024;
+const MAX_HEADERS: usize = 100;
+const MAX_BODY_LENGTH: u64 = 1024 * 1024;
+
+// An HTTP/1.x request, as it came in:
+//
+//     GET /search?q=rust HTTP/1.1\r\n
+//     Host: localhost:7878\r\n
+//     \r\n
+//
+// The body is read according to Content-Length or chunked Transfer-Encoding, so it's
+// the same bytes either way.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Request {
+    pub method: String,
+    // the path plus any query string, as it was sent
+    pub target: String,
+    pub version: String,
+    // in the order they were sent, names as they were spelled
+    pub headers: Vec<(String, String)>,
+    pub body: Vec<u8>,
+}
+
+#[derive(Debug)]
+pub enum ParseError {
+    // the connection failed, or closed before the request was complete
+    Io(io::Error),
+    // the request doesn't follow HTTP, or is over one of the limits; deserves a 400
+    BadRequest(String),
+}
+
+impl Request {
+    // Reads one request from `reader`, and nothing past its end.
+    pub fn parse(reader: &mut impl BufRead) -> Result<Request, ParseError> {
+        let line = read_line(reader)?;
+        let mut parts = line.split(' ');
+        let (Some(method), Some(target), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(bad_request(format!("malformed request line '{line}'")));
+        };
+        if !is_token(method) {
+            return Err(bad_request(format!("invalid method '{method}'")));
+        }
+        if target.is_empty() || target.bytes().any(|byte| byte.is_ascii_control()) {
+            return Err(bad_request(format!("invalid target '{target}'")));
+        }
+        if version != "HTTP/1.1" && version != "HTTP/1.0" {
+            return Err(bad_request(format!("unsupported version '{version}'")));
+        }
+
+        let mut request = Request {
+            method: method.to_string(),
+            target: target.to_string(),
+            version: version.to_string(),
+            headers: read_headers(reader)?,
+            body: Vec::new(),
+        };
+        if request.version == "HTTP/1.1" && request.header("Host").is_none() {
+            return Err(bad_request("missing Host header".to_string()));
+        }
+        request.body = request.read_body(reader)?;
+        Ok(request)
+    }
+
+    // The first header called `name`, which is case insensitive.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    // The target without its query string.
+    pub fn path(&self) -> &str {
+        self.target.split_once('?').map_or(&self.target, |(path, _)| path)
+    }
+
+    pub fn query(&self) -> Option<&str> {
+        self.target.split_once('?').map(|(_, query)| query)
+    }
+
+    fn read_body(&self, reader: &mut impl BufRead) -> Result<Vec<u8>, ParseError> {
+        let lengths: Vec<&str> = self
+            .headers
+            .iter()
+            .filter(|(name, _)| name.eq_ignore_ascii_case("Content-Length"))
+            .map(|(_, value)| value.as_str())
+            .collect();
+
+        if let Some(encoding) = self.header("Transfer-Encoding") {
+            // with both, a proxy and the server could disagree on where the body ends
+            if !lengths.is_empty() {
+                return Err(bad_request("both Content-Length and Transfer-Encoding".to_string()));
+            }
+            if !encoding.eq_ignore_ascii_case("chunked") {
+                return Err(bad_request(format!("unsupported Transfer-Encoding '{encoding}'")));
+            }
+            return read_chunked(reader);
+        }
+
+        let Some(&length) = lengths.first() else {
+            return Ok(Vec::new());
+        };
+        if lengths.iter().any(|&other| other != length) {
+            return Err(bad_request("conflicting Content-Length headers".to_string()));
+        }
+        let length = parse_length(length, 10)?;
+        let mut body = Vec::new();
+        read_exactly(reader, length, &mut body)?;
+        Ok(body)
+    }
+}
+
+// Header lines up to the empty line that ends them.
+fn read_headers(reader: &mut impl BufRead) -> Result<Vec<(String, String)>, ParseError> {
+    let mut headers = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        if line.is_empty() {
+            return Ok(headers);
+        }
+        if headers.len() == MAX_HEADERS {
+            return Err(bad_request("too many headers".to_string()));
+        }
+        // a name can't have whitespace around it, which also rules out the obsolete
+        // continuation lines that start with a space
+        let Some((name, value)) = line.split_once(':').filter(|(name, _)| is_token(name)) else {
+            return Err(bad_request(format!("malformed header '{line}'")));
+        };
+        headers.push((name.to_string(), value.trim_matches([' ', '\t']).to_string()));
+    }
+}
+
+// Chunks, each a hex size line and that many bytes, until one of size 0; then any
+// trailer headers, which are read and dropped.
+fn read_chunked(reader: &mut impl BufRead) -> Result<Vec<u8>, ParseError> {
+    let mut body = Vec::new();
+    loop {
+        let line = read_line(reader)?;
+        // chunk extensions after a ';' mean nothing to us
+        let size = line.split(';').next().unwrap_or_default().trim_matches([' ', '\t']);
+        let size = parse_length(size, 16)?;
+        if size == 0 {
+            read_headers(reader)?;
+            return Ok(body);
+        }
+        if body.len() as u64 + size > MAX_BODY_LENGTH {
+            return Err(bad_request("body too large".to_string()));
+        }
+        read_exactly(reader, size, &mut body)?;
+        if !read_line(reader)?.is_empty() {
+            return Err(bad_request("chunk longer than its size".to_string()));
+        }
+    }
+}
+
+// A line without its line ending. Lines should end in "\r\n", but a bare "\n" is
+// accepted too, as the spec suggests.
+fn read_line(reader: &mut impl BufRead) -> Result<String, ParseError> {
+    let mut line = Vec::new();
+    reader
+        .take(MAX_LINE_LENGTH)
+        .read_until(b'\n', &mut line)
+        .map_err(ParseError::Io)?;
+    if !line.ends_with(b"\n") {
+        return Err(match line.len() as u64 {
+            MAX_LINE_LENGTH => bad_request("line too long".to_string()),
+            _ => ParseError::Io(io::ErrorKind::UnexpectedEof.into()),
+        });
+    }
+    line.pop();
+    if line.ends_with(b"\r") {
+        line.pop();
+    }
+    String::from_utf8(line).map_err(|_| bad_request("line is not valid UTF-8".to_string()))
+}
+
+fn read_exactly(reader: &mut impl BufRead, length: u64, body: &mut Vec<u8>) -> Result<(), ParseError> {
+    let read = reader.take(length).read_to_end(body).map_err(ParseError::Io)?;
+    if (read as u64) < length {
+        return Err(ParseError::Io(io::ErrorKind::UnexpectedEof.into()));
+    }
+    Ok(())
+}
+
+// A Content-Length (radix 10) or chunk size (radix 16): digits only, no sign, within
+// the body limit.
+fn parse_length(text: &str, radix: u32) -> Result<u64, ParseError> {
+    let digits = !text.is_empty() && text.chars().all(|c| c.is_digit(radix));
+    match u64::from_str_radix(text, radix) {
+        Ok(length) if digits && length <= MAX_BODY_LENGTH => Ok(length),
+        Ok(_) if digits => Err(bad_request("body too large".to_string())),
+        _ => Err(bad_request(format!("invalid length '{text}'"))),
+    }
+}
+
+// The characters a method or header name can be made of.
+fn is_token(text: &str) -> bool {
+    !text.is_empty()
+        && text
+            .bytes()
+            .all(|byte| byte.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&byte))
+}
+
+fn bad_request(msg: String) -> ParseError {
+    ParseError::BadRequest(msg)
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ParseError::Io(err) => write!(f, "couldn't read request: {err}"),
+            ParseError::BadRequest(msg) => write!(f, "bad request: {msg}"),
+        }
+    }
+}
+
+impl Error for ParseError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ParseError::Io(err) => Some(err),
+            ParseError::BadRequest(_) => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(raw: &str) -> Result<Request, ParseError> {
+        Request::parse(&mut raw.as_bytes())
+    }
+
+    #[test]
+    fn request_line_and_headers() {
+        let request = parse("GET /search?q=rust HTTP/1.1\r\nHost: localhost\r\nX-Long:  a b \r\n\r\n").unwrap();
+        assert_eq!("GET", request.method);
+        assert_eq!("/search", request.path());
+        assert_eq!(Some("q=rust"), request.query());
+        assert_eq!(Some("a b"), request.header("x-long"));
+        assert!(request.body.is_empty());
+    }
+
+    #[test]
+    fn content_length_and_chunked_bodies() {
+        let request = parse("POST / HTTP/1.1\r\nHost: h\r\nContent-Length: 5\r\n\r\nhello, and more").unwrap();
+        assert_eq!(b"hello", request.body.as_slice());
+
+        let chunked = "POST / HTTP/1.1\r\nHost: h\r\nTransfer-Encoding: chunked\r\n\r\n\
+                       5;ext=1\r\nhello\r\n7\r\n, world\r\n0\r\nTrailer: x\r\n\r\n";
+        assert_eq!(b"hello, world", parse(chunked).unwrap().body.as_slice());
+    }
+
+    #[test]
+    fn malformed_requests() {
+        let bad = [
+            "GET /\r\n\r\n",
+            "GET  / HTTP/1.1\r\nHost: h\r\n\r\n",
+            "GET / HTTP/2.0\r\nHost: h\r\n\r\n",
+            "GET / HTTP/1.1\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost h\r\n\r\n",
+            "GET / HTTP/1.1\r\nHost: h\r\n folded\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: h\r\nContent-Length: -1\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: h\r\nContent-Length: 1\r\nContent-Length: 2\r\n\r\nab",
+            "POST / HTTP/1.1\r\nHost: h\r\nContent-Length: 1\r\nTransfer-Encoding: chunked\r\n\r\n",
+            "POST / HTTP/1.1\r\nHost: h\r\nTransfer-Encoding: chunked\r\n\r\nz\r\n",
+        ];
+        for raw in bad {
+            assert!(matches!(parse(raw), Err(ParseError::BadRequest(_))), "{raw:?}");
+        }
+
+        let long = format!("GET /{} HTTP/1.1\r\n\r\n", "a".repeat(MAX_LINE_LENGTH as usize));
+        assert!(matches!(parse(&long), Err(ParseError::BadRequest(_))));
+        // a request that just stops is the connection's fault, not the request's
+        assert!(matches!(parse("GET / HTTP/1.1\r\nHost: h\r\n"), Err(ParseError::Io(_))));
+    }
+}
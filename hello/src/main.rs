@@ -1,29 +1,40 @@
-use hello::ThreadPool;
+use hello::{
+    http::{ParseError, Request},
+    ThreadPool,
+};
 use std::{
     fs,
-    io::prelude::*,
+    io::{prelude::*, BufReader},
     net::{TcpListener, TcpStream},
     thread,
     time::Duration,
 };
 
 fn handle_connection(mut stream: TcpStream) {
-    let mut buffer = [0; 1024];
-    stream.read(&mut buffer).unwrap();
-
-    let get = b"GET / HTTP/1.1\r\n";
-    let sleep = b"GET /sleep HTTP/1.1\r\n";
+    let mut reader = BufReader::new(&mut stream);
 
     let http_ok = "HTTP/1.1 200 OK";
-    let http_404 = "HTTP/1.1 400 NOT FOUND";
-
-    let (status_line, filename) = if buffer.starts_with(get) {
-        (http_ok, "resources/hello.html")
-    } else if buffer.starts_with(sleep) {
-        thread::sleep(Duration::from_secs(5));
-        (http_ok, "resources/hello.html")
-    } else {
-        (http_404, "resources/404.html")
+    let http_400 = "HTTP/1.1 400 BAD REQUEST";
+    let http_404 = "HTTP/1.1 404 NOT FOUND";
+
+    let (status_line, filename) = match Request::parse(&mut reader) {
+        Ok(request) => match (request.method.as_str(), request.path()) {
+            ("GET", "/") => (http_ok, "resources/hello.html"),
+            ("GET", "/sleep") => {
+                thread::sleep(Duration::from_secs(5));
+                (http_ok, "resources/hello.html")
+            }
+            _ => (http_404, "resources/404.html"),
+        },
+        Err(ParseError::BadRequest(msg)) => {
+            println!("Bad request: {msg}");
+            (http_400, "resources/400.html")
+        }
+        // nobody left to answer
+        Err(err) => {
+            println!("{err}");
+            return;
+        }
     };
 
     let contents = fs::read_to_string(filename).unwrap();
@@ -0,0 +1,297 @@
+use std::error::Error;
+use std::fmt;
+
+pub const USAGE: &str = "\
+Usage: minigrep [OPTIONS] QUERY PATH
+       minigrep [OPTIONS] -e PATTERN... [-f FILE...] PATH
+
+Searches PATH for lines containing QUERY. PATH may be a file or a directory,
+which is searched recursively.
+
+Options:
+  -i, --ignore-case          match without regard to case (also IGNORE_CASE=1)
+  -s, --case-sensitive       match case exactly, even if IGNORE_CASE is set
+  -E, --regex                treat QUERY as a regular expression
+      --fuzzy NUM            match approximately, allowing up to NUM characters
+                             inserted, deleted or changed; each file's lines are
+                             printed closest first (in file order with context),
+                             and --json gives the distance of each
+  -w, --word-regexp          only match whole words
+  -x, --line-regexp          only match whole lines
+  -U, --multiline            let a match span lines, as with a QUERY containing a
+                             newline or -E 'a\\nb'; every line it touches is printed
+      --paragraph            match and print paragraphs (blocks of lines separated
+                             by blank lines) instead of lines
+      --record-separator SEP match and print the records between occurrences of SEP
+                             instead of lines
+      --field NAME           only match the field NAME of each record, printing the
+                             whole record: a key of a JSON object (a.b for nested
+                             ones), or a CSV column named in the header line; a
+                             quoted CSV field only spans lines if records do
+  -e, --pattern PATTERN      search for PATTERN; repeat to search for several at once
+  -f, --pattern-file FILE    search for every line of FILE as a pattern
+  -v, --invert-match         print the lines that do NOT match
+  -n, --line-number          prefix each line with its line number
+  -b, --byte-offset          prefix each line with its byte offset
+  -c, --count                only print the number of matching lines per file
+  -l, --files-with-matches   only print the names of files with a match
+  -L, --files-without-match  only print the names of files without a match
+  -q, --quiet                print nothing; only set the exit status
+      --json                 print results as JSON Lines records
+      --color[=WHEN]         highlight matches: auto (default), always or never;
+                             a bare --color is auto
+      --replace TEXT         print matching lines with every match replaced by TEXT
+      --in-place             with --replace, rewrite the files instead of printing
+      --dry-run              with --in-place, print a unified diff and change nothing
+  -A, --after-context NUM    print NUM lines after each match
+  -B, --before-context NUM   print NUM lines before each match
+  -C, --context NUM          print NUM lines before and after each match
+      --include GLOB         only search files whose name matches GLOB
+      --exclude GLOB         skip files and directories whose name matches GLOB
+      --hidden               search hidden files and directories too
+      --no-ignore            don't respect .gitignore/.ignore files
+  -j, --threads NUM          search NUM files at once (default: one per CPU)
+      --sort                 print files in path order, not as they finish; files
+                             that finish early are kept in memory until their turn
+      --stats                finish with how many files were searched, skipped and
+                             matched, the matched lines and matches, bytes searched
+                             and time taken (a \"summary\" record with --json)
+      --index                keep a trigram index of PATH (a directory) in
+                             PATH/.minigrep-index, and use it to skip files that
+                             can't contain a plain QUERY; it's updated on every run
+      --binary-files TYPE    binary (default: only say if it matches), without-match
+                             or text
+  -I                         same as --binary-files=without-match
+  -a, --text                 same as --binary-files=text
+  -z, --search-zip           search inside gzip, zstd, bzip2 and xz compressed files
+      --follow               keep searching what gets appended to PATH, like tail -f;
+                             copes with the file being truncated or rotated
+      --no-config            don't read any config file
+  -h, --help                 print this help and exit
+      --                     treat everything after this as QUERY / PATH
+
+Defaults for any option can be kept in TOML config files, using long option names
+as keys (ignore-case = true, context = 2, exclude = [\"target\", \"*.lock\"]).
+Later sources win over earlier ones:
+  1. the user file: $MINIGREP_CONFIG, or else ~/.config/minigrep/config.toml
+     (under $XDG_CONFIG_HOME if that is set)
+  2. the project file: .minigrep.toml in the current directory or the closest
+     parent directory that has one
+  3. the IGNORE_CASE environment variable
+  4. the command line
+--include and --exclude add up instead. -e, -f and the --replace options can
+only be given on the command line.
+
+With -E, ^ and $ only match at the very start and end of a paragraph, record or
+(with -U) file, and . doesn't match a newline; (?m) and (?s) change that.
+
+A file with a NUL byte in it is binary. UTF-16 files with a byte order mark are
+searched as text, and lines that aren't valid UTF-8 are read as Latin-1.
+
+Exit status is 0 if any line was selected and 1 if none was. Errors give 2 for bad
+usage or a bad config file, 3 for an invalid pattern or glob, 4 if something couldn't
+be read or written, and 5 if a file to rewrite isn't UTF-8. Files that can't be read
+are reported and skipped, and the search goes on; with -q a match still gives 0.
+";
+
+// (short name, long name, takes a value)
+const FLAGS: &[(Option<char>, &str, bool)] = &[
+    (Some('i'), "ignore-case", false),
+    (Some('s'), "case-sensitive", false),
+    (Some('E'), "regex", false),
+    (None, "fuzzy", true),
+    (Some('w'), "word-regexp", false),
+    (Some('x'), "line-regexp", false),
+    (Some('U'), "multiline", false),
+    (None, "paragraph", false),
+    (None, "record-separator", true),
+    (None, "field", true),
+    (Some('e'), "pattern", true),
+    (Some('f'), "pattern-file", true),
+    (Some('v'), "invert-match", false),
+    (Some('n'), "line-number", false),
+    (Some('b'), "byte-offset", false),
+    (Some('c'), "count", false),
+    (Some('l'), "files-with-matches", false),
+    (Some('L'), "files-without-match", false),
+    (Some('q'), "quiet", false),
+    (None, "json", false),
+    (None, "color", true),
+    (None, "replace", true),
+    (None, "in-place", false),
+    (None, "dry-run", false),
+    (Some('A'), "after-context", true),
+    (Some('B'), "before-context", true),
+    (Some('C'), "context", true),
+    (None, "include", true),
+    (None, "exclude", true),
+    (None, "hidden", false),
+    (None, "no-ignore", false),
+    (Some('j'), "threads", true),
+    (None, "sort", false),
+    (None, "index", false),
+    (None, "stats", false),
+    (None, "binary-files", true),
+    (Some('I'), "binary-without-match", false),
+    (Some('a'), "text", false),
+    (Some('z'), "search-zip", false),
+    (None, "follow", false),
+    (None, "no-config", false),
+    (Some('h'), "help", false),
+];
+
+// Flags whose value can be left out, and what they mean without one. A value for them
+// has to be attached with '=', or it couldn't be told apart from QUERY.
+const OPTIONAL_VALUES: &[(&str, &str)] = &[("color", "auto")];
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum UsageError {
+    // --help was asked for; not really an error, but it stops parsing all the same
+    Help,
+    MissingQuery,
+    MissingPath,
+    UnknownFlag(String),
+    MissingValue(String),
+    UnexpectedValue(String),
+    InvalidValue { flag: String, value: String },
+    UnexpectedArgument(String),
+    Requires { flag: String, needs: String },
+    Conflict { flag: String, with: String },
+    // a config file that can't be read or has something wrong in it, with the I/O or
+    // TOML error behind it, if that's what it was
+    ConfigFile { path: String, message: String, source: Option<Cause> },
+}
+
+// The error behind a usage error. Usage errors are compared (in tests, mostly), so two
+// of these are equal when they say the same thing.
+#[derive(Debug)]
+pub struct Cause(pub Box<dyn Error + Send + Sync>);
+
+impl PartialEq for Cause {
+    fn eq(&self, other: &Cause) -> bool {
+        self.0.to_string() == other.0.to_string()
+    }
+}
+
+impl Eq for Cause {}
+
+impl fmt::Display for UsageError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            UsageError::Help => write!(f, "help requested"),
+            UsageError::MissingQuery => write!(f, "missing QUERY argument"),
+            UsageError::MissingPath => write!(f, "missing PATH argument"),
+            UsageError::UnknownFlag(flag) => write!(f, "unknown option '{flag}'"),
+            UsageError::MissingValue(flag) => write!(f, "option '{flag}' needs a value"),
+            UsageError::UnexpectedValue(flag) => write!(f, "option '{flag}' doesn't take a value"),
+            UsageError::InvalidValue { flag, value } => {
+                write!(f, "invalid value '{value}' for option '{flag}'")
+            }
+            UsageError::UnexpectedArgument(arg) => write!(f, "unexpected argument '{arg}'"),
+            UsageError::Requires { flag, needs } => {
+                write!(f, "option '{flag}' only works together with '{needs}'")
+            }
+            UsageError::Conflict { flag, with } => {
+                write!(f, "option '{flag}' can't be used with '{with}'")
+            }
+            UsageError::ConfigFile { path, message, .. } => write!(f, "{path}: {message}"),
+        }
+    }
+}
+
+impl Error for UsageError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            UsageError::ConfigFile { source: Some(Cause(source)), .. } => Some(source.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+// The command line split into flags and positional arguments. Every flag is reported
+// under its long name, in the order it was given, so later flags can override earlier ones.
+pub struct Args {
+    pub flags: Vec<(&'static str, Option<String>)>,
+    pub positional: Vec<String>,
+}
+
+// The canonical long name of a flag and whether it takes a value.
+pub fn long_flag(name: &str) -> Option<(&'static str, bool)> {
+    FLAGS
+        .iter()
+        .find(|(_, flag, _)| *flag == name)
+        .map(|&(_, flag, takes_value)| (flag, takes_value))
+}
+
+// Accepts the usual getopt-style forms:
+//   -i -n, -in         short flags, which can be bundled
+//   -A 2, -A2          short flag with a value
+//   --context 2        long flag with a value
+//   --context=2
+//   --color            long flag whose value is optional (see OPTIONAL_VALUES)
+//   --                 everything after is positional
+// A lone "-" is positional too, so it can stand for stdin.
+pub fn parse(args: impl Iterator<Item = String>) -> Result<Args, UsageError> {
+    let mut args = args;
+    let mut flags = Vec::new();
+    let mut positional = Vec::new();
+
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            positional.extend(args.by_ref());
+            break;
+        }
+
+        if let Some(long) = arg.strip_prefix("--") {
+            let (name, inline) = match long.split_once('=') {
+                Some((name, value)) => (name, Some(value.to_string())),
+                None => (long, None),
+            };
+            let (name, takes_value) =
+                long_flag(name).ok_or_else(|| UsageError::UnknownFlag(format!("--{name}")))?;
+
+            let optional = OPTIONAL_VALUES.iter().find(|&&(flag, _)| flag == name);
+            let value = match (takes_value, inline) {
+                (true, Some(value)) => Some(value),
+                (true, None) => match optional {
+                    Some(&(_, default)) => Some(default.to_string()),
+                    None => Some(
+                        args.next()
+                            .ok_or_else(|| UsageError::MissingValue(format!("--{name}")))?,
+                    ),
+                },
+                (false, Some(_)) => return Err(UsageError::UnexpectedValue(format!("--{name}"))),
+                (false, None) => None,
+            };
+            flags.push((name, value));
+        } else if arg.len() > 1 && arg.starts_with('-') {
+            let shorts = &arg[1..];
+            for (index, c) in shorts.char_indices() {
+                let &(_, name, takes_value) = FLAGS
+                    .iter()
+                    .find(|(short, _, _)| *short == Some(c))
+                    .ok_or_else(|| UsageError::UnknownFlag(format!("-{c}")))?;
+
+                if !takes_value {
+                    flags.push((name, None));
+                    continue;
+                }
+
+                // the rest of the bundle is the value, otherwise it's the next argument
+                let rest = &shorts[index + c.len_utf8()..];
+                let value = if rest.is_empty() {
+                    args.next()
+                        .ok_or_else(|| UsageError::MissingValue(format!("-{c}")))?
+                } else {
+                    rest.to_string()
+                };
+                flags.push((name, Some(value)));
+                break;
+            }
+        } else {
+            positional.push(arg);
+        }
+    }
+
+    Ok(Args { flags, positional })
+}
@@ -0,0 +1,52 @@
+use std::env;
+use std::fmt::Display;
+use std::io::{self, IsTerminal, Write};
+
+// ANSI SGR sequences, using the same colors GNU grep does by default
+pub const PATH: &str = "35";
+pub const LINE_NUMBER: &str = "32";
+pub const BYTE_OFFSET: &str = "32";
+pub const SEPARATOR: &str = "36";
+pub const MATCH: &str = "1;31";
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ColorChoice {
+    // color only when stdout is a terminal, so pipes and files get plain text
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl ColorChoice {
+    pub fn parse(value: &str) -> Option<ColorChoice> {
+        match value {
+            "auto" => Some(ColorChoice::Auto),
+            "always" => Some(ColorChoice::Always),
+            "never" => Some(ColorChoice::Never),
+            _ => None,
+        }
+    }
+
+    pub fn enabled(self) -> bool {
+        match self {
+            ColorChoice::Always => true,
+            ColorChoice::Never => false,
+            // NO_COLOR (https://no-color.org) and dumb terminals opt out of auto coloring
+            ColorChoice::Auto => {
+                io::stdout().is_terminal()
+                    && env::var_os("NO_COLOR").is_none()
+                    && env::var("TERM").map_or(true, |term| term != "dumb")
+            }
+        }
+    }
+}
+
+// Writes `text`, wrapped in the given style if coloring is on.
+pub fn paint(out: &mut impl Write, color: bool, style: &str, text: impl Display) -> io::Result<()> {
+    if color {
+        write!(out, "\x1b[{style}m{text}\x1b[0m")
+    } else {
+        write!(out, "{text}")
+    }
+}
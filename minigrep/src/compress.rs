@@ -0,0 +1,62 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::path::Path;
+
+use bzip2::bufread::MultiBzDecoder;
+use flate2::bufread::MultiGzDecoder;
+use xz2::bufread::XzDecoder;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Zstd,
+    Bzip2,
+    Xz,
+}
+
+impl Format {
+    pub fn from_extension(path: &Path) -> Option<Format> {
+        let extension = path.extension()?.to_str()?;
+        match extension {
+            "gz" | "tgz" => Some(Format::Gzip),
+            "zst" | "zstd" | "tzst" => Some(Format::Zstd),
+            "bz2" | "tbz" | "tbz2" => Some(Format::Bzip2),
+            "xz" | "txz" => Some(Format::Xz),
+            _ => None,
+        }
+    }
+
+    pub fn from_magic(bytes: &[u8]) -> Option<Format> {
+        if bytes.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else if bytes.starts_with(b"BZh") {
+            Some(Format::Bzip2)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Format::Xz)
+        } else {
+            None
+        }
+    }
+}
+
+// For -z: `input` decompressed on the fly if it is gzip, zstd, bzip2 or xz, going by the
+// extension of `path` first and the magic bytes at the start of the data otherwise (the
+// only option for stdin). Anything else is passed through untouched. Every decoder
+// streams, so only a buffer's worth of the archive is in memory at a time, and
+// concatenated streams (as `cat a.gz b.gz` makes) are read to the end.
+pub fn reader<'a>(input: impl Read + 'a, path: Option<&Path>) -> io::Result<Box<dyn Read + 'a>> {
+    let mut input = BufReader::new(input);
+    let format = match path.and_then(Format::from_extension) {
+        Some(format) => Some(format),
+        None => Format::from_magic(input.fill_buf()?),
+    };
+
+    Ok(match format {
+        Some(Format::Gzip) => Box::new(MultiGzDecoder::new(input)),
+        Some(Format::Zstd) => Box::new(zstd::Decoder::with_buffer(input)?),
+        Some(Format::Bzip2) => Box::new(MultiBzDecoder::new(input)),
+        Some(Format::Xz) => Box::new(XzDecoder::new_multi_decoder(input)),
+        None => Box::new(input),
+    })
+}
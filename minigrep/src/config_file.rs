@@ -0,0 +1,111 @@
+use std::env;
+use std::error::Error;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml::{Table, Value};
+
+use crate::args::{self, Cause, UsageError};
+
+// The name of the per-project config file, looked for in the current directory and
+// then in each of its parents.
+pub const PROJECT_FILE: &str = ".minigrep.toml";
+
+// Options that only make sense for a single run, never as a default.
+const NOT_DEFAULTS: &[&str] = &[
+    "pattern",
+    "pattern-file",
+    "replace",
+    "in-place",
+    "dry-run",
+    "no-config",
+    "help",
+];
+
+// Defaults from the config files, as flags in the same form the command line is parsed
+// into, so they go through exactly the same handling. The user's file comes first and
+// the project's second, so when both set the same option the project wins; flags given
+// on the command line go after all of these and win over both.
+pub fn defaults() -> Result<Vec<(&'static str, Option<String>)>, UsageError> {
+    let mut flags = Vec::new();
+    for path in [user_file(), project_file()].into_iter().flatten() {
+        flags.extend(load(&path)?);
+    }
+    Ok(flags)
+}
+
+// $MINIGREP_CONFIG if it's set (an empty value means no user file at all), otherwise
+// minigrep/config.toml under $XDG_CONFIG_HOME or ~/.config. None if there's no such file.
+pub fn user_file() -> Option<PathBuf> {
+    let path = match env::var_os("MINIGREP_CONFIG") {
+        Some(path) if path.is_empty() => return None,
+        // an explicitly named file has to exist, so a typo doesn't go unnoticed
+        Some(path) => return Some(PathBuf::from(path)),
+        None => match env::var_os("XDG_CONFIG_HOME").filter(|dir| !dir.is_empty()) {
+            Some(dir) => PathBuf::from(dir),
+            None => PathBuf::from(env::var_os("HOME")?).join(".config"),
+        },
+    };
+    Some(path.join("minigrep").join("config.toml")).filter(|path| path.is_file())
+}
+
+// The closest .minigrep.toml, starting from the current directory.
+pub fn project_file() -> Option<PathBuf> {
+    let cwd = env::current_dir().ok()?;
+    cwd.ancestors()
+        .map(|dir| dir.join(PROJECT_FILE))
+        .find(|path| path.is_file())
+}
+
+// Reads one config file. Its keys are long option names, with values as follows:
+//
+//   ignore-case = true           a flag without a value; false leaves it unset
+//   context = 2                  a flag with a value
+//   color = "always"
+//   exclude = ["target", "*.lock"]   a flag that can be repeated
+pub fn load(path: &Path) -> Result<Vec<(&'static str, Option<String>)>, UsageError> {
+    let caused = |message: &str, source: Box<dyn Error + Send + Sync>| UsageError::ConfigFile {
+        path: path.display().to_string(),
+        message: message.to_string(),
+        source: Some(Cause(source)),
+    };
+    let error = |message: String| UsageError::ConfigFile {
+        path: path.display().to_string(),
+        message,
+        source: None,
+    };
+    let text = fs::read_to_string(path).map_err(|err| caused("can't read", err.into()))?;
+    let table: Table = text
+        .parse()
+        .map_err(|err: toml::de::Error| caused("not valid TOML", err.into()))?;
+
+    let mut flags = Vec::new();
+    for (key, value) in table {
+        let (name, takes_value) =
+            args::long_flag(&key).ok_or_else(|| error(format!("unknown option '{key}'")))?;
+        if NOT_DEFAULTS.contains(&name) {
+            return Err(error(format!("'{key}' can only be given on the command line")));
+        }
+
+        match (takes_value, value) {
+            (false, Value::Boolean(true)) => flags.push((name, None)),
+            (false, Value::Boolean(false)) => {}
+            (false, _) => return Err(error(format!("'{key}' should be true or false"))),
+            (true, Value::Array(values)) => {
+                for value in values {
+                    flags.push((name, Some(scalar(&key, value).map_err(error)?)));
+                }
+            }
+            (true, value) => flags.push((name, Some(scalar(&key, value).map_err(error)?))),
+        }
+    }
+    Ok(flags)
+}
+
+fn scalar(key: &str, value: Value) -> Result<String, String> {
+    match value {
+        Value::String(value) => Ok(value),
+        Value::Integer(value) => Ok(value.to_string()),
+        _ => Err(format!("'{key}' should be a string or a number")),
+    }
+}
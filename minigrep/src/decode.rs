@@ -0,0 +1,54 @@
+use std::borrow::Cow;
+use std::io::{BufReader, Read};
+
+use encoding_rs_io::{DecodeReaderBytes, DecodeReaderBytesBuilder};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryFiles {
+    // search binary files, but only say whether they match instead of printing lines
+    #[default]
+    Binary,
+    // -I: treat binary files as if nothing in them matched
+    WithoutMatch,
+    // -a: search binary files the same as text
+    Text,
+}
+
+impl BinaryFiles {
+    pub fn parse(value: &str) -> Option<BinaryFiles> {
+        match value {
+            "binary" => Some(BinaryFiles::Binary),
+            "without-match" => Some(BinaryFiles::WithoutMatch),
+            "text" => Some(BinaryFiles::Text),
+            _ => None,
+        }
+    }
+}
+
+// Wraps `inner` so a UTF-16 file with a byte order mark comes out as UTF-8, and a UTF-8
+// byte order mark is dropped. Anything without a BOM is passed through as is, invalid
+// UTF-8 included; `line` deals with that one line at a time.
+pub fn reader<R: Read>(inner: R, capacity: usize) -> BufReader<DecodeReaderBytes<R, Vec<u8>>> {
+    let decoder = DecodeReaderBytesBuilder::new()
+        .bom_sniffing(true)
+        .strip_bom(true)
+        .utf8_passthru(true)
+        .build(inner);
+    BufReader::with_capacity(capacity, decoder)
+}
+
+// Like grep, a NUL byte is taken to mean the data isn't text. Decoded UTF-16 never has
+// one, so this only catches real binary files.
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.contains(&0)
+}
+
+// A line as text: UTF-8 if it is valid UTF-8, otherwise Latin-1, where every byte is a
+// character of its own. That way a stray byte from a legacy encoding never stops the
+// search, and the rest of the line still matches as expected.
+pub fn line(bytes: &[u8]) -> Cow<'_, str> {
+    match std::str::from_utf8(bytes) {
+        Ok(text) => Cow::Borrowed(text),
+        Err(_) => Cow::Owned(bytes.iter().map(|&byte| char::from(byte)).collect()),
+    }
+}
@@ -0,0 +1,118 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::path::PathBuf;
+use std::string::FromUtf8Error;
+
+use crate::args::UsageError;
+
+// Everything that can go wrong in a run, with enough context to say where. The
+// underlying error, if there is one, is available through `Error::source`, and `chain`
+// spells out the whole story in one line.
+#[derive(Debug)]
+pub enum MinigrepError {
+    // bad flags, or a bad config file
+    Usage(UsageError),
+    // a pattern that doesn't compile; with several patterns at once this can be the
+    // alternation of all of them
+    Pattern {
+        pattern: String,
+        source: Box<dyn Error + Send + Sync>,
+    },
+    // an --include/--exclude glob that doesn't compile
+    Glob(globset::Error),
+    // reading or writing a file; stdin goes by "(standard input)"
+    Io { path: PathBuf, source: io::Error },
+    // a file that has to be text (to be rewritten with --in-place) but isn't UTF-8
+    Encoding { path: PathBuf, source: FromUtf8Error },
+    // a directory that couldn't be walked
+    Walk(ignore::Error),
+    // writing the results to stdout
+    Output(io::Error),
+}
+
+// Exit statuses besides 0 (something matched) and 1 (nothing did). grep uses 2 for
+// every kind of trouble; here 2 is still what a usage error gives, but the others
+// can be told apart.
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_PATTERN: i32 = 3;
+pub const EXIT_IO: i32 = 4;
+pub const EXIT_ENCODING: i32 = 5;
+
+impl MinigrepError {
+    pub fn io(path: impl Into<PathBuf>, source: io::Error) -> MinigrepError {
+        MinigrepError::Io { path: path.into(), source }
+    }
+
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            MinigrepError::Usage(_) => EXIT_USAGE,
+            MinigrepError::Pattern { .. } | MinigrepError::Glob(_) => EXIT_PATTERN,
+            MinigrepError::Io { .. } | MinigrepError::Walk(_) | MinigrepError::Output(_) => EXIT_IO,
+            MinigrepError::Encoding { .. } => EXIT_ENCODING,
+        }
+    }
+}
+
+impl fmt::Display for MinigrepError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MinigrepError::Usage(err) => write!(f, "{err}"),
+            MinigrepError::Pattern { pattern, .. } => write!(f, "invalid pattern '{pattern}'"),
+            MinigrepError::Glob(_) => write!(f, "invalid glob"),
+            MinigrepError::Io { path, .. } => write!(f, "{}", path.display()),
+            MinigrepError::Encoding { path, .. } => {
+                write!(f, "{}: not valid UTF-8, left unchanged", path.display())
+            }
+            MinigrepError::Walk(_) => write!(f, "can't walk directory"),
+            MinigrepError::Output(_) => write!(f, "can't write output"),
+        }
+    }
+}
+
+impl Error for MinigrepError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            MinigrepError::Usage(err) => Some(err),
+            MinigrepError::Pattern { source, .. } => Some(source.as_ref()),
+            MinigrepError::Glob(err) => Some(err),
+            MinigrepError::Io { source, .. } => Some(source),
+            MinigrepError::Encoding { source, .. } => Some(source),
+            MinigrepError::Walk(err) => Some(err),
+            MinigrepError::Output(err) => Some(err),
+        }
+    }
+}
+
+impl From<UsageError> for MinigrepError {
+    fn from(err: UsageError) -> MinigrepError {
+        MinigrepError::Usage(err)
+    }
+}
+
+impl From<globset::Error> for MinigrepError {
+    fn from(err: globset::Error) -> MinigrepError {
+        MinigrepError::Glob(err)
+    }
+}
+
+// An error followed by each of its sources, as "poem.txt: No such file or directory".
+// A usage error reads the same as the error it wraps, so that isn't repeated.
+pub fn chain(err: &MinigrepError) -> String {
+    let mut text = err.to_string();
+    let mut source = match err {
+        MinigrepError::Usage(err) => err.source(),
+        _ => err.source(),
+    };
+    while let Some(err) = source {
+        text.push_str(": ");
+        text.push_str(&err.to_string());
+        source = err.source();
+    }
+    text
+}
+
+// Prints an error that doesn't end the run, such as a file that can't be read.
+pub fn report(err: &MinigrepError) {
+    eprintln!("minigrep: {}", chain(err));
+}
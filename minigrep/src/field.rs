@@ -0,0 +1,134 @@
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+
+use serde_json::value::RawValue;
+
+// --field: finds the one part of each record that gets matched, in either of the two
+// shapes logs tend to come in. A record that starts with '{' is a JSON object, and the
+// field is the value of that key; "a.b" reaches into nested objects, unless there is a
+// key called "a.b" itself. Anything else is a CSV row, and the first of those is the
+// header that says which column the field is, by name or else by 1-based number.
+//
+// A header is found once per file, so a new `Fields` is needed for every file.
+pub struct Fields<'n> {
+    name: &'n str,
+    // once the CSV header has been seen: the column of the field, if there is one, and
+    // the delimiter
+    csv: Option<(Option<usize>, char)>,
+}
+
+pub enum Lookup<'r> {
+    // the CSV header line, which is not a record
+    Header,
+    // a record without the field
+    Missing,
+    Found(Field<'r>),
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Field<'r> {
+    // the value, with CSV quotes and JSON escapes undone; other JSON values (numbers,
+    // arrays...) as they are written
+    pub text: Cow<'r, str>,
+    // where `text` is in the record, unless undoing quotes or escapes changed it
+    pub span: Option<Range<usize>>,
+}
+
+impl<'n> Fields<'n> {
+    pub fn new(name: &'n str) -> Fields<'n> {
+        Fields { name, csv: None }
+    }
+
+    pub fn lookup<'r>(&mut self, record: &'r str) -> Lookup<'r> {
+        if record.trim_start().starts_with('{') {
+            return match json_field(record, record, self.name) {
+                Some(field) => Lookup::Found(field),
+                None => Lookup::Missing,
+            };
+        }
+
+        let Some((column, delimiter)) = self.csv else {
+            // a header that's all tabs and no commas is from a TSV file
+            let delimiter = if record.contains('\t') && !record.contains(',') { '\t' } else { ',' };
+            let names = csv_fields(record, delimiter);
+            let column = names
+                .iter()
+                .position(|name| name.text.trim() == self.name)
+                .or_else(|| self.name.parse::<usize>().ok().filter(|&n| n > 0).map(|n| n - 1));
+            self.csv = Some((column, delimiter));
+            return Lookup::Header;
+        };
+        match column.and_then(|column| csv_fields(record, delimiter).into_iter().nth(column)) {
+            Some(field) => Lookup::Found(field),
+            None => Lookup::Missing,
+        }
+    }
+}
+
+// The field `name` of the JSON object in `object`, which is a part of `record`. Values
+// are borrowed straight from the record, so where they are is known without searching.
+fn json_field<'r>(record: &'r str, object: &'r str, name: &str) -> Option<Field<'r>> {
+    let fields: HashMap<String, &RawValue> = serde_json::from_str(object).ok()?;
+    let Some(value) = fields.get(name) else {
+        let (outer, inner) = name.split_once('.')?;
+        return json_field(record, fields.get(outer)?.get(), inner);
+    };
+
+    let raw = value.get();
+    let start = raw.as_ptr() as usize - record.as_ptr() as usize;
+    if !raw.starts_with('"') {
+        return Some(Field { text: Cow::Borrowed(raw), span: Some(start..start + raw.len()) });
+    }
+    if raw.contains('\\') {
+        let text: String = serde_json::from_str(raw).ok()?;
+        return Some(Field { text: Cow::Owned(text), span: None });
+    }
+    let text = &raw[1..raw.len() - 1];
+    Some(Field { text: Cow::Borrowed(text), span: Some(start + 1..start + 1 + text.len()) })
+}
+
+// Splits a CSV row into its fields. A field in double quotes can hold the delimiter and
+// quotes (written twice); anything after the closing quote up to the next delimiter is
+// dropped. It can only hold a line break when records aren't lines to begin with
+// (--paragraph, --record-separator), since a row is never more than one record.
+fn csv_fields(record: &str, delimiter: char) -> Vec<Field<'_>> {
+    let mut fields = Vec::new();
+    let mut start = 0;
+    loop {
+        let rest = &record[start..];
+        let end = if let Some(quoted) = rest.strip_prefix('"') {
+            let mut close = 0;
+            loop {
+                match quoted[close..].find('"') {
+                    Some(found) if quoted[close + found + 1..].starts_with('"') => close += found + 2,
+                    Some(found) => {
+                        close += found;
+                        break;
+                    }
+                    // no closing quote: the field runs to the end
+                    None => {
+                        close = quoted.len();
+                        break;
+                    }
+                }
+            }
+            let text = &quoted[..close];
+            fields.push(if text.contains("\"\"") {
+                Field { text: Cow::Owned(text.replace("\"\"", "\"")), span: None }
+            } else {
+                Field { text: Cow::Borrowed(text), span: Some(start + 1..start + 1 + close) }
+            });
+            start + 1 + close
+        } else {
+            let length = rest.find(delimiter).unwrap_or(rest.len());
+            fields.push(Field { text: Cow::Borrowed(&rest[..length]), span: Some(start..start + length) });
+            start + length
+        };
+
+        match record[end..].find(delimiter) {
+            Some(next) => start = end + next + delimiter.len_utf8(),
+            None => return fields,
+        }
+    }
+}
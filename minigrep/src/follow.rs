@@ -0,0 +1,119 @@
+use std::fmt;
+use std::fs::{self, File, Metadata};
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::Duration;
+
+// how often to look for new data once the end of the file is reached
+pub const POLL_INTERVAL: Duration = Duration::from_millis(250);
+
+type OnChange = Box<dyn FnMut(&Path, Change)>;
+
+// A file being written to, read like `tail -f`: reading starts at the current end, and
+// instead of ever reporting the end of the file, a read waits for more to be appended.
+// That makes it an ordinary reader for the rest of the search, which never has to know
+// the data is still growing.
+//
+// Log files don't only grow, though. If the file shrinks it was truncated, and reading
+// starts over from the top. If `path` turns out to be a different file than the one
+// open (a log rotated away and a fresh one created in its place), the rest of the old
+// file is read first and then the new one from the top. Either way `on_change` is told,
+// so whoever is following can let the user know.
+pub struct Follow {
+    path: PathBuf,
+    file: File,
+    position: u64,
+    identity: Option<(u64, u64)>,
+    interval: Duration,
+    on_change: OnChange,
+}
+
+// Why reading went back to the start of the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Change {
+    // a different file has the path now, as after a log rotation
+    Replaced,
+    Truncated,
+}
+
+impl fmt::Display for Change {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Change::Replaced => write!(f, "file replaced; following the new file"),
+            Change::Truncated => write!(f, "file truncated"),
+        }
+    }
+}
+
+impl Follow {
+    pub fn open(
+        path: &Path,
+        interval: Duration,
+        on_change: impl FnMut(&Path, Change) + 'static,
+    ) -> io::Result<Follow> {
+        let mut file = File::open(path)?;
+        let position = file.seek(SeekFrom::End(0))?;
+        let identity = identity(&file.metadata()?);
+
+        Ok(Follow {
+            path: path.to_path_buf(),
+            file,
+            position,
+            identity,
+            interval,
+            on_change: Box::new(on_change),
+        })
+    }
+
+    // Whether the file was swapped out or cut short, in which case reading goes on from
+    // the start of what's there now. A path that's missing for the moment, as it is
+    // part way through a rotation, is nothing to act on yet.
+    fn reopen_if_changed(&mut self) -> io::Result<bool> {
+        let Ok(metadata) = fs::metadata(&self.path) else {
+            return Ok(false);
+        };
+
+        let change = if identity(&metadata) != self.identity {
+            self.file = File::open(&self.path)?;
+            self.identity = identity(&self.file.metadata()?);
+            Change::Replaced
+        } else if metadata.len() < self.position {
+            self.file.seek(SeekFrom::Start(0))?;
+            Change::Truncated
+        } else {
+            return Ok(false);
+        };
+        self.position = 0;
+        (self.on_change)(&self.path, change);
+        Ok(true)
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let read = self.file.read(buf)?;
+            if read > 0 || buf.is_empty() {
+                self.position += read as u64;
+                return Ok(read);
+            }
+            if !self.reopen_if_changed()? {
+                thread::sleep(self.interval);
+            }
+        }
+    }
+}
+
+// What tells two files apart even when they've had the same name: the device and inode
+// on Unix. Elsewhere there's nothing as reliable, so only truncation is noticed there.
+#[cfg(unix)]
+fn identity(metadata: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((metadata.dev(), metadata.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_metadata: &Metadata) -> Option<(u64, u64)> {
+    None
+}
@@ -0,0 +1,52 @@
+use std::ops::Range;
+
+// Approximate substring search: the part of `text` that is the fewest edits (characters
+// inserted, deleted or swapped for another) away from `pattern`, as long as that's at
+// most `max_distance`. Returns the distance and the byte range of that part of `text`;
+// of several equally close ones, the one that ends first wins.
+//
+// This is Sellers' variant of the edit distance table, where a match may start anywhere
+// in `text` for free. It takes time proportional to the length of the pattern times the
+// length of the text, which is fine for lines but not for whole files.
+pub fn find(
+    pattern: &[char],
+    text: &str,
+    ignore_case: bool,
+    max_distance: usize,
+) -> Option<(usize, Range<usize>)> {
+    let fold = |c: char| match ignore_case {
+        true => c.to_lowercase().next().unwrap_or(c),
+        false => c,
+    };
+    let m = pattern.len();
+
+    // column[i] is the cheapest way to match pattern[..i] ending at the current position
+    // of `text`: (number of edits, byte offset in `text` where that match starts)
+    let mut column: Vec<(usize, usize)> = (0..=m).map(|i| (i, 0)).collect();
+    let mut best = (m <= max_distance).then_some((m, 0..0));
+
+    for (index, c) in text.char_indices() {
+        let end = index + c.len_utf8();
+        let c = fold(c);
+        let mut diagonal = column[0];
+        column[0] = (0, end);
+        for i in 1..=m {
+            let swap = (diagonal.0 + usize::from(fold(pattern[i - 1]) != c), diagonal.1);
+            let insert = (column[i].0 + 1, column[i].1);
+            let delete = (column[i - 1].0 + 1, column[i - 1].1);
+            diagonal = column[i];
+            // min_by_key keeps the first of equals, so a swap wins a tie
+            column[i] = [swap, delete, insert].into_iter().min_by_key(|&(edits, _)| edits).unwrap();
+        }
+
+        let (edits, start) = column[m];
+        if edits <= max_distance && best.as_ref().is_none_or(|(distance, _)| edits < *distance) {
+            best = Some((edits, start..end));
+            if edits == 0 {
+                break;
+            }
+        }
+    }
+
+    best
+}
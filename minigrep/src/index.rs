@@ -0,0 +1,249 @@
+use std::collections::{BTreeMap, BTreeSet};
+use std::fs::{self, File};
+use std::io::{self, BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::UNIX_EPOCH;
+
+use crate::decode;
+use crate::error::{self, MinigrepError};
+use crate::matcher::case_fold;
+use crate::parser::{Config, OutputMode};
+
+// Where the index of a directory is kept, inside the directory itself.
+pub const INDEX_FILE: &str = ".minigrep-index";
+
+// the start of every index file, with a version number for when the format changes
+const MAGIC: &[u8] = b"minigrep-index 1\n";
+
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+// anything longer means the index is corrupt, rather than a reason to allocate it
+const MAX_PATH_LENGTH: u64 = 64 * 1024;
+
+// A trigram index for --index: for every file, the set of three-byte sequences that
+// occur within its lines. A file can only contain a pattern if it contains every
+// trigram of that pattern, so checking those sets is enough to rule most files out
+// without reading them; the ones left are searched as usual, which weeds out the rest.
+//
+// The text is case folded before it's indexed, which makes the same index work for both
+// case sensitive and -i searches. Files are keyed by their path relative to the root,
+// along with the size and modification time they had when they were indexed, so only
+// new or changed files have to be read again on the next run.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub struct Index {
+    files: BTreeMap<PathBuf, Entry>,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+struct Entry {
+    size: u64,
+    modified: (u64, u32),
+    // sorted, so a lookup is a binary search
+    trigrams: Vec<u32>,
+}
+
+// The files out of `files` (found by walking `root`) that could match, using and then
+// updating the index in `root`. Whenever the query doesn't allow ruling anything out,
+// such as a regex or -v, that's all of them, but the index is still brought up to date.
+// An index that can't be saved (a read-only directory, say) is reported, and this run
+// goes on with the one in memory.
+pub fn candidates(root: &Path, config: &Config, patterns: &[String], files: Vec<PathBuf>) -> Vec<PathBuf> {
+    let index_path = root.join(INDEX_FILE);
+    // a missing or unreadable index is simply built again from scratch
+    let mut index = Index::load(&index_path).unwrap_or_default();
+    if index.update(root, &files) {
+        if let Err(err) = index.save(&index_path) {
+            error::report(&MinigrepError::io(&index_path, err));
+        }
+    }
+
+    let Some(needed) = needed_trigrams(config, patterns) else {
+        return files;
+    };
+    files
+        .into_iter()
+        .filter(|path| {
+            let key = path.strip_prefix(root).unwrap_or(path);
+            index.may_contain(key, &needed)
+        })
+        .collect()
+}
+
+// The trigrams of each pattern, or None when the index can't help: a regex or fuzzy
+// query doesn't have to contain any of its own text, and neither does a --field once
+// its escapes are undone; -v selects lines *without* it,
+// -c and -L have to print something for every file, and -z would need the
+// decompressed text indexed. Lines are indexed one by one, so a pattern spanning lines
+// needs the trigrams of each of its lines. A pattern without any trigrams (shorter
+// than three bytes on every line) could be in any file.
+fn needed_trigrams(config: &Config, patterns: &[String]) -> Option<Vec<Vec<u32>>> {
+    let prunes = matches!(
+        config.output,
+        OutputMode::Lines | OutputMode::Json | OutputMode::FilesWithMatches | OutputMode::Quiet
+    );
+    let plain = !config.regex && config.fuzzy.is_none() && config.field.is_none();
+    if !prunes || !plain || config.invert_match || config.search_zip {
+        return None;
+    }
+
+    let needed: Vec<Vec<u32>> = patterns
+        .iter()
+        .map(|pattern| {
+            let pattern = case_fold(pattern);
+            pattern.split('\n').flat_map(trigrams).collect::<BTreeSet<u32>>().into_iter().collect()
+        })
+        .collect();
+    needed.iter().all(|trigrams| !trigrams.is_empty()).then_some(needed)
+}
+
+impl Index {
+    // Whether the file at `key` might contain any of the patterns, given the trigrams of
+    // each. A file the index doesn't know about might contain anything.
+    pub fn may_contain(&self, key: &Path, needed: &[Vec<u32>]) -> bool {
+        let Some(entry) = self.files.get(key) else {
+            return true;
+        };
+        needed.iter().any(|pattern| {
+            pattern
+                .iter()
+                .all(|trigram| entry.trigrams.binary_search(trigram).is_ok())
+        })
+    }
+
+    // Re-indexes every file in `files` that is new or has changed since it was indexed,
+    // and forgets the ones that are gone. Returns whether anything changed.
+    pub fn update(&mut self, root: &Path, files: &[PathBuf]) -> bool {
+        let mut changed = false;
+        let mut seen = BTreeSet::new();
+
+        for path in files {
+            let key = path.strip_prefix(root).unwrap_or(path).to_path_buf();
+            let Ok(metadata) = fs::metadata(path) else {
+                continue;
+            };
+            let size = metadata.len();
+            let modified = metadata
+                .modified()
+                .ok()
+                .and_then(|time| time.duration_since(UNIX_EPOCH).ok())
+                .map_or((0, 0), |time| (time.as_secs(), time.subsec_nanos()));
+            seen.insert(key.clone());
+
+            let current = self.files.get(&key);
+            if current.is_some_and(|entry| entry.size == size && entry.modified == modified) {
+                continue;
+            }
+            // a file that can't be read stays out of the index, so it's always searched
+            // and the search gets to report the error
+            match file_trigrams(path) {
+                Ok(trigrams) => {
+                    self.files.insert(key, Entry { size, modified, trigrams });
+                }
+                Err(_) => {
+                    self.files.remove(&key);
+                }
+            }
+            changed = true;
+        }
+
+        let before = self.files.len();
+        self.files.retain(|key, _| seen.contains(key));
+        changed || self.files.len() != before
+    }
+
+    pub fn load(path: &Path) -> io::Result<Index> {
+        let mut reader = BufReader::new(File::open(path)?);
+        let mut magic = vec![0; MAGIC.len()];
+        reader.read_exact(&mut magic)?;
+        if magic != MAGIC {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, "not a minigrep index"));
+        }
+
+        let mut index = Index::default();
+        let files = read_u64(&mut reader)?;
+        for _ in 0..files {
+            let length = read_u64(&mut reader)?;
+            if length > MAX_PATH_LENGTH {
+                return Err(io::Error::new(io::ErrorKind::InvalidData, "corrupt index"));
+            }
+            let mut key = vec![0; length as usize];
+            reader.read_exact(&mut key)?;
+            let key = String::from_utf8(key)
+                .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            let size = read_u64(&mut reader)?;
+            let modified = (read_u64(&mut reader)?, read_u64(&mut reader)? as u32);
+            let count = read_u64(&mut reader)?;
+            let mut trigrams = Vec::new();
+            for _ in 0..count {
+                let mut bytes = [0; 4];
+                reader.read_exact(&mut bytes)?;
+                trigrams.push(u32::from_le_bytes(bytes));
+            }
+            index.files.insert(PathBuf::from(key), Entry { size, modified, trigrams });
+        }
+        Ok(index)
+    }
+
+    // Written to a temporary file first and then renamed into place, so a search running
+    // at the same time never sees half an index.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let temp = path.with_file_name(format!("{INDEX_FILE}.{}", process::id()));
+        let result = self.write(&temp).and_then(|()| fs::rename(&temp, path));
+        if result.is_err() {
+            let _ = fs::remove_file(&temp);
+        }
+        result
+    }
+
+    fn write(&self, path: &Path) -> io::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        out.write_all(MAGIC)?;
+        out.write_all(&(self.files.len() as u64).to_le_bytes())?;
+        for (key, entry) in &self.files {
+            let key = key.to_string_lossy();
+            out.write_all(&(key.len() as u64).to_le_bytes())?;
+            out.write_all(key.as_bytes())?;
+            out.write_all(&entry.size.to_le_bytes())?;
+            out.write_all(&entry.modified.0.to_le_bytes())?;
+            out.write_all(&u64::from(entry.modified.1).to_le_bytes())?;
+            out.write_all(&(entry.trigrams.len() as u64).to_le_bytes())?;
+            for trigram in &entry.trigrams {
+                out.write_all(&trigram.to_le_bytes())?;
+            }
+        }
+        out.into_inner()?.sync_all()
+    }
+}
+
+// Every trigram in the file, read the same way the search reads it: decoded line by
+// line (see `decode`) and then case folded.
+fn file_trigrams(path: &Path) -> io::Result<Vec<u32>> {
+    let mut reader = decode::reader(File::open(path)?, READ_BUFFER_SIZE);
+    let mut found = BTreeSet::new();
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        if reader.read_until(b'\n', &mut buffer)? == 0 {
+            break;
+        }
+        found.extend(trigrams(&case_fold(&decode::line(&buffer))));
+    }
+    Ok(found.into_iter().collect())
+}
+
+// Every run of three bytes in `text`, packed into a u32. A line ending is never part of
+// a match, so it isn't indexed either.
+fn trigrams(text: &str) -> BTreeSet<u32> {
+    let text = text.trim_end_matches(['\n', '\r']);
+    text.as_bytes()
+        .windows(3)
+        .map(|bytes| u32::from(bytes[0]) << 16 | u32::from(bytes[1]) << 8 | u32::from(bytes[2]))
+        .collect()
+}
+
+fn read_u64(reader: &mut impl Read) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
@@ -0,0 +1,102 @@
+use std::io::{self, Write};
+use std::path::Path;
+
+use serde_json::{json, Value};
+
+use crate::matcher::Hit;
+use crate::searcher::SinkLine;
+use crate::stats::Stats;
+
+// JSON Lines output for --json: one object per line, each with a "type" field.
+//
+//   {"type":"begin","path":"src/lib.rs"}
+//   {"type":"match","path":"src/lib.rs","line_number":3,"byte_offset":41,
+//    "line":"...","submatches":[{"match":"query","pattern":0,"start":8,"end":13}]}
+//   {"type":"context", ...same fields as "match", with no submatches}
+//   {"type":"end","path":"src/lib.rs","matched_lines":1}
+//   {"type":"summary","files_searched":1, ...the --stats counts}
+//
+// "byte_offset" is where the line starts in the file; submatch "start"/"end" are byte
+// offsets within "line", and "pattern" is the index of the pattern that hit, counting
+// the QUERY or -e patterns first and then the lines of any -f files. A file only gets
+// begin/end records if something in it was printed, and "path" is null for stdin.
+// With --fuzzy, match records also carry the "distance" of the closest hit.
+
+pub fn begin(out: &mut impl Write, path: Option<&Path>) -> io::Result<()> {
+    write_record(out, json!({ "type": "begin", "path": path_value(path) }))
+}
+
+pub fn line(
+    out: &mut impl Write,
+    is_match: bool,
+    path: Option<&Path>,
+    line: &SinkLine,
+    submatches: &[Hit],
+    distance: Option<usize>,
+) -> io::Result<()> {
+    let SinkLine { line_number, byte_offset, line, .. } = *line;
+    let submatches: Vec<Value> = submatches
+        .iter()
+        .map(|hit| {
+            json!({
+                "match": &line[hit.span.clone()],
+                "pattern": hit.pattern,
+                "start": hit.span.start,
+                "end": hit.span.end,
+            })
+        })
+        .collect();
+
+    let mut record = json!({
+        "type": if is_match { "match" } else { "context" },
+        "path": path_value(path),
+        "line_number": line_number,
+        "byte_offset": byte_offset,
+        "line": line,
+        "submatches": submatches,
+    });
+    if let Some(distance) = distance {
+        record["distance"] = Value::from(distance);
+    }
+    write_record(out, record)
+}
+
+pub fn end(out: &mut impl Write, path: Option<&Path>, matched_lines: usize) -> io::Result<()> {
+    write_record(
+        out,
+        json!({
+            "type": "end",
+            "path": path_value(path),
+            "matched_lines": matched_lines,
+        }),
+    )
+}
+
+// The --stats record, after everything else.
+pub fn summary(out: &mut impl Write, stats: &Stats) -> io::Result<()> {
+    write_record(
+        out,
+        json!({
+            "type": "summary",
+            "files_searched": stats.files_searched,
+            "files_skipped": stats.files_skipped,
+            "files_matched": stats.files_matched,
+            "matched_lines": stats.matched_lines,
+            "matches": stats.matches,
+            "bytes_searched": stats.bytes_searched,
+            "elapsed_seconds": stats.elapsed.as_secs_f64(),
+        }),
+    )
+}
+
+fn path_value(path: Option<&Path>) -> Value {
+    match path {
+        Some(path) => Value::from(path.to_string_lossy()),
+        None => Value::Null,
+    }
+}
+
+fn write_record(out: &mut impl Write, record: Value) -> io::Result<()> {
+    serde_json::to_writer(&mut *out, &record)?;
+    writeln!(out)
+}
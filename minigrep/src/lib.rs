@@ -1,66 +1,948 @@
+pub mod args;
+pub mod color;
+pub mod compress;
+pub mod config_file;
+pub mod decode;
+pub mod error;
+pub mod field;
+pub mod follow;
+pub mod index;
+pub mod fuzzy;
+pub mod json;
+pub mod matcher;
+pub mod pool;
+pub mod replace;
+pub mod searcher;
+pub mod stats;
+pub mod walk;
+
 pub mod parser {
+    use std::collections::BTreeMap;
     use std::env;
-    use std::error::Error;
-    use std::fs;
+    use std::fs::{self, File};
+    use std::io::{self, BufRead, BufWriter, Read, Write};
+    use std::mem;
+    use std::path::{Path, PathBuf};
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::mpsc::{self, SyncSender};
+    use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+    use std::thread;
+    use std::time::Instant;
+
+    use crate::args::{self, UsageError};
+    use crate::color::{self, ColorChoice};
+    use crate::config_file;
+    use crate::compress;
+    use crate::decode::{self, BinaryFiles};
+    use crate::error::{self, MinigrepError};
+    use crate::field::{Fields, Lookup};
+    use crate::follow::{self, Follow};
+    use crate::index;
+    use crate::json;
+    use crate::matcher::{Boundary, Hit, Matcher};
+    use crate::pool::ThreadPool;
+    use crate::replace;
+    use crate::searcher::{
+        trim_line_ending, FileSummary, Ranked, Records, Searcher, SearcherBuilder, Sink, SinkLine,
+    };
+    use crate::stats::Stats;
+    use crate::walk;
 
+    #[derive(Debug, Default)]
     pub struct Config {
+        // empty when the patterns come from -e/-f instead
         pub query: String,
+        // -e patterns, searched for together with the lines of any -f files
+        pub patterns: Vec<String>,
+        pub pattern_files: Vec<String>,
         pub file_path: String,
         pub ignore_case: bool,
+        // treat the query as a regular expression instead of a plain substring
+        pub regex: bool,
+        // match approximately, within this many edits of the query
+        pub fuzzy: Option<usize>,
+        // only count hits that are whole words (-w) or whole lines (-x)
+        pub boundary: Boundary,
+        // match lines, the whole input at once (-U), paragraphs or custom records
+        pub records: Records,
+        // only match this field of each JSON or CSV record
+        pub field: Option<String>,
+        // only search files whose name matches one of these globs (directory search)
+        pub include: Vec<String>,
+        // skip files and directories whose name matches one of these globs
+        pub exclude: Vec<String>,
+        // also search hidden files and directories
+        pub hidden: bool,
+        // don't respect .gitignore/.ignore files
+        pub no_ignore: bool,
+        // prefix every printed line with its 1-based line number
+        pub line_number: bool,
+        // prefix every printed line with the byte offset of its start
+        pub byte_offset: bool,
+        // lines of context to print before/after each match
+        pub before_context: usize,
+        pub after_context: usize,
+        // print the lines that don't match instead
+        pub invert_match: bool,
+        // what to print for each file searched
+        pub output: OutputMode,
+        // how many files to search at once; None picks one thread per CPU
+        pub threads: Option<usize>,
+        // print files in path order instead of in the order they finish
+        pub sort: bool,
+        // when to highlight matches, paths and line numbers
+        pub color: ColorChoice,
+        // print matching lines with every hit swapped for this text
+        pub replace: Option<String>,
+        // write the replaced lines back to the files instead of printing them
+        pub in_place: bool,
+        // with --in-place, only print a unified diff of what would change
+        pub dry_run: bool,
+        // what to do with files that look binary
+        pub binary: BinaryFiles,
+        // -z: search inside gzip, zstd, bzip2 and xz compressed files
+        pub search_zip: bool,
+        // keep the file open and search whatever is appended to it, like tail -f
+        pub follow: bool,
+        // use (and keep up to date) a trigram index of the directory to skip files
+        pub index: bool,
+        // report how much was searched and found once the run is over
+        pub stats: bool,
+    }
+
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+    pub enum OutputMode {
+        // the selected lines themselves, plus any context
+        #[default]
+        Lines,
+        // -c: how many lines were selected
+        Count,
+        // -l: the names of files with at least one selected line
+        FilesWithMatches,
+        // -L: the names of files without any
+        FilesWithoutMatch,
+        // -q: nothing at all, only the exit status
+        Quiet,
+        // --json: the selected lines and context as JSON Lines records
+        Json,
+    }
+
+    // What a whole run turned up, for main to turn into an exit status.
+    #[derive(Debug, Default)]
+    pub struct Outcome {
+        // whether any line was selected (or with --in-place, any file changed)
+        pub matched: bool,
+        // files that couldn't be read or written; each was reported and skipped
+        pub errors: Vec<MinigrepError>,
+        // what was searched and found, for --stats
+        pub stats: Stats,
+    }
+
+    // A single matching line, as a `Searcher` would hand it to a `Sink`, and the first
+    // hit in it.
+    #[derive(Debug, PartialEq, Eq)]
+    pub struct Match<'a> {
+        pub line: SinkLine<'a>,
+        pub hit: Hit,
+    }
+
+    impl<'a> Match<'a> {
+        pub fn matched(&self) -> &'a str {
+            &self.line.line[self.hit.span.clone()]
+        }
     }
 
     impl Config {
+        // Flags can come before, between or after QUERY and PATH, and for repeated flags the
+        // last one wins. Defaults come from the config files unless --no-config is given;
+        // see `with_defaults` for how they are weighed against the rest.
         pub fn build(mut args: impl Iterator<Item = String>,
-        ) -> Result<Config, &'static str> {
+        ) -> Result<Config, UsageError> {
             args.next();
 
-            let query = match args.next() {
-                Some(arg) => arg,
-                None => return Err("Didn't get a query string"),
-            };
-            let file_path = match args.next() {
-                Some(arg) => arg,
-                None => return Err("Didn't get a file path"),
+            let parsed = args::parse(args)?;
+            // a broken config file shouldn't stand in the way of finding out how to fix it
+            if parsed.flags.iter().any(|(flag, _)| *flag == "help") {
+                return Err(UsageError::Help);
+            }
+            let defaults = if parsed.flags.iter().any(|(flag, _)| *flag == "no-config") {
+                Vec::new()
+            } else {
+                config_file::defaults()?
             };
+            Config::with_defaults(defaults, env::var("IGNORE_CASE").is_ok(), parsed)
+        }
 
-            let ignore_case = env::var("IGNORE_CASE").is_ok();
+        // `defaults` (flags read from config files) are applied first, then
+        // `ignore_case_env` (whether the IGNORE_CASE environment variable is set), and then
+        // the command line, so each of them overrides the ones before.
+        pub fn with_defaults(
+            defaults: Vec<(&'static str, Option<String>)>,
+            ignore_case_env: bool,
+            parsed: args::Args,
+        ) -> Result<Config, UsageError> {
+            let mut config = Config::default();
+            for (flag, value) in defaults {
+                config.apply(flag, value)?;
+            }
+            if ignore_case_env {
+                config.ignore_case = true;
+            }
+            for (flag, value) in parsed.flags {
+                config.apply(flag, value)?;
+            }
 
-            Ok(Config { 
-                query,
-                file_path,
-                ignore_case,
-            })
+            let mut positional = parsed.positional.into_iter();
+            // like grep, -e/-f replace the QUERY argument, so the first positional is PATH
+            if config.patterns.is_empty() && config.pattern_files.is_empty() {
+                config.query = positional.next().ok_or(UsageError::MissingQuery)?;
+            }
+            config.file_path = positional.next().ok_or(UsageError::MissingPath)?;
+            if let Some(extra) = positional.next() {
+                return Err(UsageError::UnexpectedArgument(extra));
+            }
+
+            if config.fuzzy.is_some() && config.regex {
+                return Err(UsageError::Conflict {
+                    flag: "--fuzzy".to_string(),
+                    with: "--regex".to_string(),
+                });
+            }
+            if config.in_place && config.replace.is_none() {
+                return Err(UsageError::Requires {
+                    flag: "--in-place".to_string(),
+                    needs: "--replace".to_string(),
+                });
+            }
+            // -v selects the lines that don't match, which have nothing to replace
+            if config.in_place && config.invert_match {
+                return Err(UsageError::Conflict {
+                    flag: "--in-place".to_string(),
+                    with: "--invert-match".to_string(),
+                });
+            }
+            if config.dry_run && !config.in_place {
+                return Err(UsageError::Requires {
+                    flag: "--dry-run".to_string(),
+                    needs: "--in-place".to_string(),
+                });
+            }
+            // multiline mode reads everything before it matches, and a followed file never ends
+            if config.follow && config.records == Records::Multiline {
+                return Err(UsageError::Conflict {
+                    flag: "--follow".to_string(),
+                    with: "--multiline".to_string(),
+                });
+            }
+            if config.field.is_some() && (config.records == Records::Multiline || config.in_place) {
+                return Err(UsageError::Conflict {
+                    flag: "--field".to_string(),
+                    with: if config.in_place { "--in-place" } else { "--multiline" }.to_string(),
+                });
+            }
+            if config.in_place && config.records != Records::Lines {
+                return Err(UsageError::Conflict {
+                    flag: "--in-place".to_string(),
+                    with: match config.records {
+                        Records::Multiline => "--multiline",
+                        Records::Paragraphs => "--paragraph",
+                        _ => "--record-separator",
+                    }
+                    .to_string(),
+                });
+            }
+            if config.follow && (config.in_place || config.file_path == "-") {
+                return Err(UsageError::Conflict {
+                    flag: "--follow".to_string(),
+                    with: if config.in_place { "--in-place" } else { "- (standard input)" }.to_string(),
+                });
+            }
+            if config.in_place && config.stats {
+                return Err(UsageError::Conflict {
+                    flag: "--in-place".to_string(),
+                    with: "--stats".to_string(),
+                });
+            }
+            if config.in_place && config.search_zip {
+                return Err(UsageError::Conflict {
+                    flag: "--in-place".to_string(),
+                    with: "--search-zip".to_string(),
+                });
+            }
+            if config.in_place && config.file_path == "-" {
+                return Err(UsageError::Conflict {
+                    flag: "--in-place".to_string(),
+                    with: "- (standard input)".to_string(),
+                });
+            }
+
+            Ok(config)
         }
 
-        pub fn parse_config(args: &[String]) -> Result<Config, &'static str> {
+        // One flag, by its long name.
+        fn apply(&mut self, flag: &'static str, value: Option<String>) -> Result<(), UsageError> {
+            let value = value.unwrap_or_default();
+            match flag {
+                "ignore-case" => self.ignore_case = true,
+                "case-sensitive" => self.ignore_case = false,
+                "regex" => self.regex = true,
+                "fuzzy" => self.fuzzy = Some(parse_count(flag, value)?),
+                "word-regexp" => self.boundary = Boundary::Word,
+                "line-regexp" => self.boundary = Boundary::Line,
+                "multiline" => self.records = Records::Multiline,
+                "paragraph" => self.records = Records::Paragraphs,
+                "record-separator" if value.is_empty() => {
+                    return Err(UsageError::InvalidValue { flag: format!("--{flag}"), value })
+                }
+                "record-separator" => self.records = Records::Separator(value),
+                "field" => self.field = Some(value),
+                "invert-match" => self.invert_match = true,
+                "line-number" => self.line_number = true,
+                "byte-offset" => self.byte_offset = true,
+                "count" => self.output = OutputMode::Count,
+                "files-with-matches" => self.output = OutputMode::FilesWithMatches,
+                "files-without-match" => self.output = OutputMode::FilesWithoutMatch,
+                "quiet" => self.output = OutputMode::Quiet,
+                "json" => self.output = OutputMode::Json,
+                "replace" => self.replace = Some(value),
+                "in-place" => self.in_place = true,
+                "dry-run" => self.dry_run = true,
+                "binary-files" => {
+                    self.binary = BinaryFiles::parse(&value).ok_or_else(|| {
+                        UsageError::InvalidValue { flag: format!("--{flag}"), value }
+                    })?;
+                }
+                "binary-without-match" => self.binary = BinaryFiles::WithoutMatch,
+                "text" => self.binary = BinaryFiles::Text,
+                "search-zip" => self.search_zip = true,
+                "follow" => self.follow = true,
+                "index" => self.index = true,
+                "stats" => self.stats = true,
+                "pattern" => self.patterns.push(value),
+                "pattern-file" => self.pattern_files.push(value),
+                "threads" => match parse_count(flag, value)? {
+                    0 => {
+                        return Err(UsageError::InvalidValue {
+                            flag: format!("--{flag}"),
+                            value: "0".to_string(),
+                        })
+                    }
+                    threads => self.threads = Some(threads),
+                },
+                "sort" => self.sort = true,
+                "color" => {
+                    self.color = ColorChoice::parse(&value).ok_or_else(|| {
+                        UsageError::InvalidValue { flag: format!("--{flag}"), value }
+                    })?;
+                }
+                "after-context" => self.after_context = parse_count(flag, value)?,
+                "before-context" => self.before_context = parse_count(flag, value)?,
+                "context" => {
+                    let count = parse_count(flag, value)?;
+                    self.before_context = count;
+                    self.after_context = count;
+                }
+                "include" => self.include.push(value),
+                "exclude" => self.exclude.push(value),
+                "hidden" => self.hidden = true,
+                "no-ignore" => self.no_ignore = true,
+                "no-config" => {}
+                "help" => return Err(UsageError::Help),
+                _ => unreachable!("flag --{flag} is declared but not handled"),
+            }
+            Ok(())
+        }
+
+        pub fn parse_config(args: &[String]) -> Result<Config, UsageError> {
             // clone has a runtime cost and more efficient methods of fixing ownership will be shown
             // in Chapter 13
-            if args.len() < 3 {
-                return Err("Not enough arguments");
+            Config::build(args.iter().cloned())
+        }
+
+        // Everything to search for: QUERY, or else the -e patterns followed by each
+        // line of the -f files.
+        pub fn patterns(&self) -> Result<Vec<String>, MinigrepError> {
+            if self.patterns.is_empty() && self.pattern_files.is_empty() {
+                return Ok(vec![self.query.clone()]);
             }
-            let query = args[1].clone();
-            let file_path = args[2].clone();
-            let ignore_case = env::var("IGNORE_CASE").is_ok();
 
-            Ok(Config { query, file_path, ignore_case })
+            let mut patterns = self.patterns.clone();
+            for path in &self.pattern_files {
+                let contents = fs::read_to_string(path).map_err(|err| MinigrepError::io(path, err))?;
+                patterns.extend(contents.lines().map(String::from));
+            }
+            Ok(patterns)
+        }
+
+        // The search part of the config, without anything about what to print.
+        pub fn searcher(&self) -> Result<Searcher, MinigrepError> {
+            SearcherBuilder::new()
+                .patterns(self.patterns()?)
+                .ignore_case(self.ignore_case)
+                .regex(self.regex)
+                .fuzzy(self.fuzzy)
+                .boundary(self.boundary)
+                .invert_match(self.invert_match)
+                .before_context(self.before_context)
+                .after_context(self.after_context)
+                .binary(self.binary)
+                .records(self.records.clone())
+                .field(self.field.clone())
+                .count_matches(self.stats)
+                .build()
         }
 
-        pub fn run(config: Config) -> Result<(), Box<dyn Error>> {
-            let contents = fs::read_to_string(config.file_path)?;
+        // Per-file errors don't end the run: they are reported on stderr, the file is
+        // skipped, and they are collected in `Outcome::errors` for main to pick an exit
+        // status from. Errors that leave nothing to search (a bad pattern, a missing
+        // file or directory to search) or nowhere to print to end it.
+        pub fn run(config: Config) -> Result<Outcome, MinigrepError> {
+            // a single locked handle instead of locking stdout for every line
+            Config::run_to(config, io::stdout().lock())
+        }
+
+        // `run`, printing to `out` instead of stdout.
+        pub fn run_to(config: Config, out: impl Write) -> Result<Outcome, MinigrepError> {
+            let started = Instant::now();
+            // build the searcher once up front rather than once per file
+            let searcher = config.searcher()?;
+            // with --follow every line has to show up as soon as it's found
+            let capacity = if config.follow { 0 } else { OUTPUT_BUFFER_SIZE };
+            let mut out = BufWriter::with_capacity(capacity, Output { inner: out, failed: false });
 
-            if config.ignore_case == true {
-                for line in search_case_insensitive(&config.query, &contents) {
-                    println!("{line}");
+            let root = Path::new(&config.file_path);
+            if config.follow && root.is_dir() {
+                return Err(MinigrepError::Usage(UsageError::Conflict {
+                    flag: "--follow".to_string(),
+                    with: "a directory".to_string(),
+                }));
+            }
+            if config.in_place {
+                let mut walk = if root.is_dir() {
+                    walk::files(root, &config)?
+                } else {
+                    walk::Walk { files: vec![root.to_path_buf()], errors: Vec::new() }
+                };
+                let mut outcome = replace::rewrite_files(&config, searcher.matcher(), &walk.files, &mut out)?;
+                outcome.errors.append(&mut walk.errors);
+                out.flush().map_err(MinigrepError::Output)?;
+                return Ok(outcome);
+            }
+
+            if config.file_path == "-" || !root.is_dir() {
+                // "-" reads from stdin, so minigrep can sit at the end of a pipe
+                let (name, path) = if config.file_path == "-" {
+                    ("(standard input)", None)
+                } else {
+                    (config.file_path.as_str(), Some(root))
+                };
+                let reader = open(&config, path).map_err(|err| MinigrepError::io(name, err))?;
+                // JSON records always carry the path, even for a single file
+                let prefix = path.filter(|_| config.output == OutputMode::Json);
+
+                // reading and printing are interleaved here, so it takes the output
+                // itself to tell which one failed
+                let summary = search_reader(&config, &searcher, prefix, reader, &mut out)
+                    .map_err(|err| match out.get_ref().failed {
+                        true => MinigrepError::Output(err),
+                        false => MinigrepError::io(name, err),
+                    })?;
+                print_summary(&config, name, false, summary, &mut out).map_err(MinigrepError::Output)?;
+                let mut outcome = Outcome { matched: summary.count > 0, ..Outcome::default() };
+                outcome.stats.add(&summary, skipped_as_binary(&config, &summary));
+                finish_run(&config, &mut outcome, started, &mut out)?;
+                return Ok(outcome);
+            }
+
+            // a directory is walked recursively and every hit is prefixed with its path
+            let mut walk = walk::files(root, &config)?;
+            let walked = walk.files.len();
+            if config.index {
+                walk.files = index::candidates(root, &config, &config.patterns()?, walk.files);
+            }
+            let config = Arc::new(config);
+            let mut outcome = search_files(Arc::clone(&config), searcher, walk.files, &mut out)?;
+            // that includes the files the index ruled out, and any never got to after -q
+            outcome.stats.files_skipped = walked - outcome.stats.files_searched;
+            walk.errors.append(&mut outcome.errors);
+            outcome.errors = walk.errors;
+            finish_run(&config, &mut outcome, started, &mut out)?;
+            Ok(outcome)
+        }
+    }
+
+    // The end of a search: the --stats report if it was asked for, and then whatever
+    // output is still buffered.
+    fn finish_run(
+        config: &Config,
+        outcome: &mut Outcome,
+        started: Instant,
+        out: &mut impl Write,
+    ) -> Result<(), MinigrepError> {
+        outcome.stats.elapsed = started.elapsed();
+        if config.stats {
+            match config.output {
+                OutputMode::Json => json::summary(out, &outcome.stats),
+                _ => outcome.stats.print(out),
+            }
+            .map_err(MinigrepError::Output)?;
+        }
+        out.flush().map_err(MinigrepError::Output)
+    }
+
+    // With -I, a binary file is only looked at long enough to tell that it's binary.
+    fn skipped_as_binary(config: &Config, summary: &FileSummary) -> bool {
+        summary.binary && config.binary == BinaryFiles::WithoutMatch
+    }
+
+    // Where the results go, remembering whether writing to it ever failed.
+    struct Output<W> {
+        inner: W,
+        failed: bool,
+    }
+
+    impl<W: Write> Write for Output<W> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.inner.write(buf).inspect_err(|_| self.failed = true)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            self.inner.flush().inspect_err(|_| self.failed = true)
+        }
+    }
+
+    // Searches `files` on a pool of worker threads. Each file's output is collected into
+    // its own buffer and written out in one go, so lines from different files never
+    // interleave; with --sort the buffers are also written in the order of `files`.
+    // Without --sort a file with a lot of output has it written out as it goes instead
+    // (see `Spill`), but with --sort every file that finishes before its turn is kept
+    // in memory until then.
+    fn search_files(
+        config: Arc<Config>,
+        searcher: Searcher,
+        files: Vec<PathBuf>,
+        out: &mut impl Write,
+    ) -> Result<Outcome, MinigrepError> {
+        let threads = config
+            .threads
+            .unwrap_or_else(|| thread::available_parallelism().map_or(1, |n| n.get()))
+            .min(files.len())
+            .max(1);
+        let sort = config.sort;
+        let quiet = config.output == OutputMode::Quiet;
+
+        let searcher = Arc::new(searcher);
+        // set once the outcome is decided, so queued files are skipped instead of searched
+        let stop = Arc::new(AtomicBool::new(false));
+        let floor = Arc::new(Mutex::new(()));
+        let pool = ThreadPool::new(threads);
+        // however this returns, even when the output fails, and before waiting on the pool
+        let _stop = StopOnDrop(Arc::clone(&stop));
+        // bounded, so a file being spilled can't get further ahead of the output than this
+        let (sender, receiver) = mpsc::sync_channel(threads);
+
+        for (index, path) in files.into_iter().enumerate() {
+            let config = Arc::clone(&config);
+            let searcher = Arc::clone(&searcher);
+            let stop = Arc::clone(&stop);
+            let floor = Arc::clone(&floor);
+            let sender = sender.clone();
+
+            pool.execute(move || {
+                if stop.load(Ordering::Relaxed) {
+                    return;
                 }
+                let mut output =
+                    Spill { buffer: Vec::new(), sender: &sender, floor: &floor, held: None, spill: !sort };
+                let result = search_file(&config, &searcher, &path, &mut output)
+                    .map_err(|err| MinigrepError::io(path, err));
+                output.done(index, result);
+            });
+        }
+        drop(sender);
+
+        let mut outcome = Outcome::default();
+        let mut pending = BTreeMap::new();
+        let mut next = 0;
+        for message in receiver {
+            let (index, result) = match message {
+                Message::Output(output) => {
+                    out.write_all(&output).map_err(MinigrepError::Output)?;
+                    continue;
+                }
+                Message::Done(index, result) => (index, result),
+            };
+            let ready = if sort {
+                pending.insert(index, result);
+                let mut ready = Vec::new();
+                while let Some(result) = pending.remove(&next) {
+                    ready.push(result);
+                    next += 1;
+                }
+                ready
             } else {
-                for line in search(&config.query, &contents) {
-                    println!("{line}");
+                vec![result]
+            };
+
+            for result in ready {
+                match result {
+                    Ok((summary, buffer)) => {
+                        out.write_all(&buffer).map_err(MinigrepError::Output)?;
+                        outcome.matched |= summary.count > 0;
+                        outcome.stats.add(&summary, skipped_as_binary(&config, &summary));
+                    }
+                    // one unreadable file shouldn't hide the results from all the others
+                    Err(err) => {
+                        error::report(&err);
+                        outcome.errors.push(err);
+                        outcome.stats.files_skipped += 1;
+                    }
                 }
             }
+
+            // with -q the answer is known as soon as anything matches
+            if outcome.matched && quiet {
+                break;
+            }
+        }
+
+        Ok(outcome)
+    }
+
+    // Sets the flag when dropped.
+    struct StopOnDrop(Arc<AtomicBool>);
+
+    impl Drop for StopOnDrop {
+        fn drop(&mut self) {
+            self.0.store(true, Ordering::Relaxed);
+        }
+    }
+
+    // What the workers in `search_files` send back.
+    enum Message {
+        // the start of a file's output, when there's too much of it to hold on to; the
+        // rest follows, up to the file's `Done`, without anything else in between
+        Output(Vec<u8>),
+        // a file's index in `files` and the rest of its output
+        Done(usize, Result<(FileSummary, Vec<u8>), MinigrepError>),
+    }
+
+    // One file's worth of output, buffered so it can be written out as a single block.
+    // Once `spill` lets it grow past SPILL_SIZE it's sent on as it comes instead, with
+    // `floor` held until the file is done: every file needs it to send its `Done`, so
+    // no other file's output can get in between.
+    struct Spill<'a> {
+        buffer: Vec<u8>,
+        sender: &'a SyncSender<Message>,
+        floor: &'a Mutex<()>,
+        held: Option<MutexGuard<'a, ()>>,
+        spill: bool,
+    }
+
+    impl Spill<'_> {
+        fn done(mut self, index: usize, result: Result<FileSummary, MinigrepError>) {
+            let _floor = match self.held.take() {
+                Some(held) => held,
+                None => self.floor.lock().unwrap_or_else(PoisonError::into_inner),
+            };
+            let result = result.map(|summary| (summary, mem::take(&mut self.buffer)));
+            // the receiving end only goes away once we've stopped caring
+            let _ = self.sender.send(Message::Done(index, result));
+        }
+    }
+
+    impl Write for Spill<'_> {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.buffer.extend_from_slice(buf);
+            if self.spill && self.buffer.len() >= SPILL_SIZE {
+                if self.held.is_none() {
+                    self.held = Some(self.floor.lock().unwrap_or_else(PoisonError::into_inner));
+                }
+                let output = mem::take(&mut self.buffer);
+                self.sender
+                    .send(Message::Output(output))
+                    .map_err(|_| io::Error::from(io::ErrorKind::BrokenPipe))?;
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
             Ok(())
         }
     }
 
+    fn search_file(
+        config: &Config,
+        searcher: &Searcher,
+        path: &Path,
+        out: &mut impl Write,
+    ) -> io::Result<FileSummary> {
+        let reader = open(config, Some(path))?;
+        let summary = search_reader(config, searcher, Some(path), reader, out)?;
+        print_summary(config, &path.display().to_string(), true, summary, out)?;
+        Ok(summary)
+    }
+
+    const READ_BUFFER_SIZE: usize = 64 * 1024;
+    const OUTPUT_BUFFER_SIZE: usize = 8 * 1024;
+    // how much of one file's output `search_files` holds on to
+    const SPILL_SIZE: usize = 1024 * 1024;
+
+    // A file, or stdin for None, ready to be searched: decompressed first with -z, then
+    // decoded (see `decode::reader`).
+    fn open(config: &Config, path: Option<&Path>) -> io::Result<impl BufRead> {
+        let input: Box<dyn Read> = match path {
+            Some(path) if config.follow => {
+                // a notice rather than an error: following goes on
+                let notice = |path: &Path, change| eprintln!("minigrep: {}: {change}", path.display());
+                Box::new(Follow::open(path, follow::POLL_INTERVAL, notice)?)
+            }
+            Some(path) => Box::new(File::open(path)?),
+            None => Box::new(io::stdin().lock()),
+        };
+        let input = if config.search_zip {
+            compress::reader(input, path)?
+        } else {
+            input
+        };
+        Ok(decode::reader(input, READ_BUFFER_SIZE))
+    }
+
+    fn parse_count(flag: &str, value: String) -> Result<usize, UsageError> {
+        value.parse().map_err(|_| UsageError::InvalidValue {
+            flag: format!("--{flag}"),
+            value,
+        })
+    }
+
+    // Runs `searcher` over `reader` and prints what it finds to `out`, as set up by
+    // `config`. `path` is the prefix for every printed line, if they get one.
+    //
+    // Lines are only printed in OutputMode::Lines and OutputMode::Json, and the modes that
+    // only care whether there is a match stop reading at the first one. Once the data
+    // looks binary nothing more is printed and the search ends at the next match;
+    // `print_summary` then says so.
+    pub fn search_reader(
+        config: &Config,
+        searcher: &Searcher,
+        path: Option<&Path>,
+        reader: impl BufRead,
+        out: &mut impl Write,
+    ) -> io::Result<FileSummary> {
+        let mut printer = LinePrinter {
+            config,
+            path,
+            out,
+            color: config.color.enabled(),
+            last_printed: None,
+            binary: false,
+        };
+        // --fuzzy prints each file's lines closest first, unless context has to go in
+        // between them or they have to show up as they're found
+        let ranked = config.fuzzy.is_some()
+            && matches!(config.output, OutputMode::Lines | OutputMode::Json)
+            && config.before_context == 0
+            && config.after_context == 0
+            && !config.follow;
+        if ranked {
+            searcher.search_reader(reader, &mut Ranked::new(printer))
+        } else {
+            searcher.search_reader(reader, &mut printer)
+        }
+    }
+
+    // The per-file line for the modes that don't print lines (-c, -l, -L), and the note
+    // that stands in for the lines of a binary file that matched.
+    fn print_summary(
+        config: &Config,
+        name: &str,
+        with_path: bool,
+        summary: FileSummary,
+        out: &mut impl Write,
+    ) -> io::Result<()> {
+        let color = config.color.enabled();
+        let count = summary.count;
+        match config.output {
+            OutputMode::Lines if summary.binary && count > 0 => {
+                writeln!(out, "Binary file {name} matches")
+            }
+            OutputMode::Count => {
+                if with_path {
+                    color::paint(out, color, color::PATH, name)?;
+                    color::paint(out, color, color::SEPARATOR, ':')?;
+                }
+                writeln!(out, "{count}")
+            }
+            OutputMode::FilesWithMatches if count > 0 => {
+                color::paint(out, color, color::PATH, name)?;
+                writeln!(out)
+            }
+            OutputMode::FilesWithoutMatch if count == 0 => {
+                color::paint(out, color, color::PATH, name)?;
+                writeln!(out)
+            }
+            _ => Ok(()),
+        }
+    }
+
+    // Prints grep style: matching lines use ':' after the prefix fields, context lines
+    // use '-', and non-adjacent groups of lines are separated by "--". In JSON mode the
+    // same lines become "match"/"context" records instead.
+    struct LinePrinter<'a, W> {
+        config: &'a Config,
+        path: Option<&'a Path>,
+        out: &'a mut W,
+        color: bool,
+        last_printed: Option<usize>,
+        binary: bool,
+    }
+
+    impl<W: Write> Sink for LinePrinter<'_, W> {
+        fn matched(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+            match self.config.output {
+                OutputMode::FilesWithMatches | OutputMode::FilesWithoutMatch | OutputMode::Quiet => {
+                    Ok(false)
+                }
+                OutputMode::Count => Ok(true),
+                OutputMode::Lines | OutputMode::Json if self.binary => Ok(false),
+                OutputMode::Lines | OutputMode::Json => {
+                    self.print(searcher, ':', line)?;
+                    Ok(true)
+                }
+            }
+        }
+
+        fn context(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+            if matches!(self.config.output, OutputMode::Lines | OutputMode::Json) {
+                self.print(searcher, '-', line)?;
+            }
+            Ok(true)
+        }
+
+        fn binary(&mut self, _searcher: &Searcher, _line_number: usize) -> io::Result<bool> {
+            self.binary = true;
+            Ok(true)
+        }
+
+        fn finish(&mut self, _searcher: &Searcher, summary: &FileSummary) -> io::Result<()> {
+            if self.config.output == OutputMode::Json && self.last_printed.is_some() {
+                json::end(self.out, self.path, summary.count)?;
+            }
+            Ok(())
+        }
+    }
+
+    impl<W: Write> LinePrinter<'_, W> {
+        fn print(&mut self, searcher: &Searcher, separator: char, line: &SinkLine) -> io::Result<()> {
+            if self.config.output == OutputMode::Json {
+                return self.print_json(searcher, separator == ':', line);
+            }
+
+            let out = &mut *self.out;
+            let color = self.color;
+            let with_context = self.config.before_context > 0 || self.config.after_context > 0;
+            // paragraphs and records are kept apart in the output the same way they were
+            // in the input
+            let record_separator = match searcher.records() {
+                Records::Paragraphs => Some(""),
+                Records::Separator(separator) => Some(trim_line_ending(separator)),
+                Records::Lines | Records::Multiline => None,
+            };
+            if let Some(separator) = record_separator {
+                if self.last_printed.is_some() {
+                    color::paint(out, color, color::SEPARATOR, separator)?;
+                    writeln!(out)?;
+                }
+            } else if with_context && self.last_printed.is_some_and(|last| last + 1 < line.line_number) {
+                color::paint(out, color, color::SEPARATOR, "--")?;
+                writeln!(out)?;
+            }
+            self.last_printed = Some(line.line_number);
+
+            if let Some(path) = self.path {
+                color::paint(out, color, color::PATH, path.display())?;
+                color::paint(out, color, color::SEPARATOR, separator)?;
+            }
+            if self.config.line_number {
+                color::paint(out, color, color::LINE_NUMBER, line.line_number)?;
+                color::paint(out, color, color::SEPARATOR, separator)?;
+            }
+            if self.config.byte_offset {
+                color::paint(out, color, color::BYTE_OFFSET, line.byte_offset)?;
+                color::paint(out, color, color::SEPARATOR, separator)?;
+            }
+
+            // only touch real hits; an inverted match has nothing in it to point at
+            let text = line.line;
+            let rewrite = color || self.config.replace.is_some();
+            if rewrite && separator == ':' && !searcher.invert_match() {
+                let mut written = 0;
+                for hit in searcher.hits(line) {
+                    let shown = match &self.config.replace {
+                        Some(replacement) => replacement.as_str(),
+                        None => &text[hit.span.clone()],
+                    };
+                    write!(out, "{}", &text[written..hit.span.start])?;
+                    color::paint(out, color, color::MATCH, shown)?;
+                    written = hit.span.end;
+                }
+                writeln!(out, "{}", &text[written..])
+            } else {
+                writeln!(out, "{text}")
+            }
+        }
+
+        fn print_json(&mut self, searcher: &Searcher, is_match: bool, line: &SinkLine) -> io::Result<()> {
+            if self.last_printed.is_none() {
+                json::begin(self.out, self.path)?;
+            }
+            self.last_printed = Some(line.line_number);
+
+            // an inverted match is a line where the query *doesn't* occur
+            let hit = is_match && !searcher.invert_match();
+            let submatches = if hit { searcher.hits(line) } else { Vec::new() };
+            let distance = match hit && self.config.fuzzy.is_some() {
+                true => searcher.matcher().distance(line.field.clone().map_or(line.line, |field| &line.line[field])),
+                false => None,
+            };
+            json::line(self.out, is_match, self.path, line, &submatches, distance)
+        }
+    }
+
+    // Same as str::lines (a trailing "\r" is dropped too), but also yields the byte
+    // offset each line starts at.
+    fn lines_with_offsets(contents: &str) -> impl Iterator<Item = (usize, &str)> {
+        let mut offset = 0;
+        contents.split_inclusive('\n').map(move |line| {
+            let start = offset;
+            offset += line.len();
+            (start, trim_line_ending(line))
+        })
+    }
+
+    // Like `search`, but keeps track of where each hit is.
+    pub fn find_matches<'a>(matcher: &Matcher, contents: &'a str) -> Vec<Match<'a>> {
+        lines_with_offsets(contents)
+            .enumerate()
+            .filter_map(|(index, (byte_offset, line))| {
+                matcher.find(line).map(|hit| Match {
+                    line: SinkLine { line_number: index + 1, byte_offset, line, field: None },
+                    hit,
+                })
+            })
+            .collect()
+    }
+
+    // The records of CSV (after its header line) or JSON Lines `contents` whose `field`
+    // contains `query`, as whole lines.
+    pub fn search_field<'a>(query: &str, field: &str, contents: &'a str) -> Vec<&'a str> {
+        let mut fields = Fields::new(field);
+        contents
+            .lines()
+            .filter(|line| matches!(fields.lookup(line), Lookup::Found(field) if field.text.contains(query)))
+            .collect()
+    }
+
     pub fn search<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
         contents
             .lines()
@@ -68,17 +950,45 @@ pub mod parser {
             .collect()
     }
 
+    // Uses the same Unicode case folding as `Matcher::ignore_case`, and returns the lines
+    // exactly as they appear in `contents`.
     pub fn search_case_insensitive<'a>(query: &str, contents: &'a str) -> Vec<&'a str> {
-        let query = query.to_lowercase();
-        let mut results = Vec::new();
+        search_with(&Matcher::ignore_case(query), contents)
+    }
 
-        for line in contents.lines() {
-            if line.to_lowercase().contains(&query) {
-                results.push(line.trim());
-            }
-        }
+    // The regex variants compile the query once and then reuse it for every line.
+    // An invalid pattern is reported back instead of panicking.
+    pub fn search_regex<'a>(query: &str, contents: &'a str) -> Result<Vec<&'a str>, regex::Error> {
+        let matcher = Matcher::new(query, false, true)?;
+        Ok(search_with(&matcher, contents))
+    }
+
+    pub fn search_regex_case_insensitive<'a>(
+        query: &str,
+        contents: &'a str,
+    ) -> Result<Vec<&'a str>, regex::Error> {
+        let matcher = Matcher::new(query, true, true)?;
+        Ok(search_with(&matcher, contents))
+    }
+
+    // Lines that contain `query` give or take `max_distance` edits (characters inserted,
+    // deleted or swapped), closest first, each with its distance. Lines that are equally
+    // close stay in the order they appear in `contents`.
+    pub fn search_fuzzy<'a>(query: &str, contents: &'a str, max_distance: usize) -> Vec<(usize, &'a str)> {
+        let matcher = Matcher::fuzzy(&[query], false, max_distance);
+        let mut found: Vec<(usize, &str)> = contents
+            .lines()
+            .filter_map(|line| matcher.distance(line).map(|distance| (distance, line)))
+            .collect();
+        found.sort_by_key(|&(distance, _)| distance);
+        found
+    }
 
-        return results;
+    fn search_with<'a>(matcher: &Matcher, contents: &'a str) -> Vec<&'a str> {
+        contents
+            .lines()
+            .filter(|line| matcher.is_match(line))
+            .collect()
     }
 }
 
@@ -90,9 +1000,9 @@ mod tests {
     fn case_sensitive() {
         let query = "duct";
         let contents = "\
-        Rust:
-        safe, fast, productive.
-        Pick three.";
+Rust:
+safe, fast, productive.
+Pick three.";
         assert_eq!(vec!["safe, fast, productive."], parser::search(query, contents));
     }
 
@@ -100,10 +1010,10 @@ mod tests {
     fn case_insensitive() {
         let query = "rUsT";
         let contents = "\
-        Rust:
-        safe, fast, productive.
-        Pick three.
-        Trust me.";
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
         assert_eq!(vec!["Rust:", "Trust me."],
             parser::search_case_insensitive(query, contents)
         );
@@ -113,10 +1023,729 @@ mod tests {
     fn one_result() {
         let query = "duct";
         let contents = "\
-        Rust:
-        safe, fast, productive.
-        Pick three.";
+Rust:
+safe, fast, productive.
+Pick three.";
 
         assert_eq!(vec!["safe, fast, productive."], parser::search(query, contents));
     }
+
+    #[test]
+    fn regex_case_sensitive() {
+        let query = r"^(Rust|Pick)\b";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(vec!["Rust:", "Pick three."],
+            parser::search_regex(query, contents).unwrap()
+        );
+    }
+
+    #[test]
+    fn regex_case_insensitive() {
+        let query = r"^[a-z]+ (three|me)\.$";
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        assert_eq!(vec!["Pick three.", "Trust me."],
+            parser::search_regex_case_insensitive(query, contents).unwrap()
+        );
+    }
+
+    #[test]
+    fn regex_invalid_pattern() {
+        assert!(parser::search_regex("(unclosed", "Rust:").is_err());
+    }
+
+    #[test]
+    fn walk_include_exclude() {
+        let root = TempDir::new("walk");
+        std::fs::create_dir_all(root.join("src/skip")).unwrap();
+        std::fs::create_dir_all(root.join(".hidden")).unwrap();
+        for file in ["src/a.rs", "src/b.txt", "src/skip/c.rs", ".hidden/d.rs", "e.rs"] {
+            std::fs::write(root.join(file), "Rust:").unwrap();
+        }
+
+        let args = ["minigrep", "--no-config", "Rust", root.to_str().unwrap(), "--include", "*.rs", "--exclude=skip"];
+        let config = parser::Config::build(args.iter().map(|arg| arg.to_string())).unwrap();
+        let walk = walk::files(&root, &config).unwrap();
+
+        assert_eq!(vec![root.join("e.rs"), root.join("src/a.rs")], walk.files);
+    }
+
+    #[test]
+    fn match_positions() {
+        let matcher = matcher::Matcher::new("fast", false, false).unwrap();
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.";
+
+        let matches = parser::find_matches(&matcher, contents);
+        assert_eq!(1, matches.len());
+        assert_eq!(2, matches[0].line.line_number);
+        assert_eq!(6, matches[0].line.byte_offset);
+        assert_eq!(6..10, matches[0].hit.span);
+        assert_eq!("fast", matches[0].matched());
+    }
+
+    #[test]
+    fn match_span_case_insensitive() {
+        let matcher = matcher::Matcher::new("STRASSE", true, false).unwrap();
+        let contents = "Die İnsel, die Strasse.";
+
+        let matches = parser::find_matches(&matcher, contents);
+        assert_eq!("Strasse", matches[0].matched());
+    }
+
+    // config files and the environment of the machine running the tests must not change
+    // the results
+    fn build(args: &[&str]) -> Result<parser::Config, args::UsageError> {
+        let parsed = args::parse(args[1..].iter().map(|arg| arg.to_string()))?;
+        parser::Config::with_defaults(Vec::new(), false, parsed)
+    }
+
+    // What searching `input` prints, set up by the command line `args`.
+    fn output(args: &[&str], input: &str) -> String {
+        let config = build(args).unwrap();
+        let mut out = Vec::new();
+        parser::search_reader(&config, &config.searcher().unwrap(), None, input.as_bytes(), &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    // A directory of a test's own, removed again when it goes out of scope, also when the
+    // test fails.
+    struct TempDir(std::path::PathBuf);
+
+    impl TempDir {
+        fn new(name: &str) -> TempDir {
+            let path = std::env::temp_dir().join(format!("minigrep_{name}_{}", std::process::id()));
+            let _ = std::fs::remove_dir_all(&path);
+            std::fs::create_dir_all(&path).unwrap();
+            TempDir(path)
+        }
+    }
+
+    impl std::ops::Deref for TempDir {
+        type Target = std::path::Path;
+
+        fn deref(&self) -> &std::path::Path {
+            &self.0
+        }
+    }
+
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn flags_bundled_and_attached() {
+        let config = build(&["minigrep", "-inA2", "query", "--", "-file"]).unwrap();
+
+        assert!(config.ignore_case);
+        assert!(config.line_number);
+        assert_eq!(2, config.after_context);
+        assert_eq!("query", config.query);
+        assert_eq!("-file", config.file_path);
+    }
+
+    #[test]
+    fn flag_overrides_env() {
+        let with_env = |args: &[&str]| {
+            let parsed = args::parse(args.iter().map(|arg| arg.to_string())).unwrap();
+            parser::Config::with_defaults(Vec::new(), true, parsed).unwrap()
+        };
+        let from_env = with_env(&["query", "poem.txt"]);
+        let from_flag = with_env(&["--case-sensitive", "query", "poem.txt"]);
+
+        assert!(from_env.ignore_case);
+        assert!(!from_flag.ignore_case);
+    }
+
+    #[test]
+    fn usage_errors_name_the_argument() {
+        assert_eq!(
+            args::UsageError::InvalidValue { flag: "--context".to_string(), value: "x".to_string() },
+            build(&["minigrep", "--context=x", "query", "poem.txt"]).unwrap_err()
+        );
+        assert_eq!(
+            args::UsageError::UnknownFlag("-Z".to_string()),
+            build(&["minigrep", "-Z", "query", "poem.txt"]).unwrap_err()
+        );
+        assert_eq!(
+            args::UsageError::MissingPath,
+            build(&["minigrep", "query"]).unwrap_err()
+        );
+        // without --no-config, so help comes before the config files are even read
+        let help = parser::Config::build(["minigrep", "-n", "--help"].into_iter().map(String::from));
+        assert_eq!(args::UsageError::Help, help.unwrap_err());
+    }
+
+    #[test]
+    fn streaming_context() {
+        let poem = std::fs::read_to_string("poem.txt").unwrap();
+
+        assert_eq!("\
+5-
+6:How dreary to be somebody!
+7:How public, like a frog
+8-To tell your name the livelong day
+", output(&["minigrep", "-n", "-C1", "How", "-"], &poem));
+
+        // the context kept around only grows with the lines that are actually there
+        assert_eq!("a\nabc\n", output(&["minigrep", "-B", "1000000000", "-C", "1000000000", "b", "-"], "a\nabc\n"));
+    }
+
+    #[test]
+    fn output_modes_count_and_quiet() {
+        let poem = std::fs::read("poem.txt").unwrap();
+
+        let config = build(&["minigrep", "-vc", "nobody", "poem.txt"]).unwrap();
+        let searcher = config.searcher().unwrap();
+        let mut out = Vec::new();
+        let count = parser::search_reader(&config, &searcher, None, poem.as_slice(), &mut out).unwrap().count;
+        assert_eq!(7, count);
+        assert!(out.is_empty());
+
+        let config = build(&["minigrep", "-q", "o", "poem.txt"]).unwrap();
+        let searcher = config.searcher().unwrap();
+        let count = parser::search_reader(&config, &searcher, None, poem.as_slice(), &mut out).unwrap().count;
+        assert_eq!(1, count);
+        assert!(out.is_empty());
+    }
+
+    #[test]
+    fn json_records() {
+        assert_eq!("\
+{\"type\":\"begin\",\"path\":null}
+{\"type\":\"match\",\"path\":null,\"line_number\":1,\"byte_offset\":0,\"line\":\"Who? Oh.\",\"submatches\":[\
+{\"match\":\"o\",\"pattern\":0,\"start\":2,\"end\":3},{\"match\":\"O\",\"pattern\":0,\"start\":5,\"end\":6}]}
+{\"type\":\"end\",\"path\":null,\"matched_lines\":1}
+", output(&["minigrep", "--json", "-i", "o", "poem.txt"], "Who? Oh.\nRust"));
+    }
+
+    #[test]
+    fn pool_runs_every_job() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        {
+            let pool = pool::ThreadPool::new(3);
+            for job in 0..10 {
+                let sender = sender.clone();
+                pool.execute(move || sender.send(job).unwrap());
+            }
+        }
+        drop(sender);
+
+        let mut done: Vec<i32> = receiver.iter().collect();
+        done.sort();
+        assert_eq!((0..10).collect::<Vec<_>>(), done);
+    }
+
+    #[test]
+    fn color_highlights_matches() {
+        assert_eq!(
+            "\x1b[32m1\x1b[0m\x1b[36m:\x1b[0mWh\x1b[1;31mo\x1b[0m? Oh.\n",
+            output(&["minigrep", "--color=always", "-n", "o", "poem.txt"], "Who? Oh.\nRust")
+        );
+
+        // like grep, a bare --color is auto, and doesn't take the next argument as WHEN
+        let config = build(&["minigrep", "--color=never", "--color", "never", "poem.txt"]).unwrap();
+        assert_eq!(color::ColorChoice::Auto, config.color);
+        assert_eq!(("never", "poem.txt"), (config.query.as_str(), config.file_path.as_str()));
+    }
+
+    #[test]
+    fn case_insensitive_unicode_folding() {
+        // spelled out with \n so the leading spaces survive
+        let contents = "  Die Straße ist lang.\nİstanbul\nΣΊΣΥΦΟΣ";
+
+        assert_eq!(vec!["  Die Straße ist lang."],
+            parser::search_case_insensitive("STRASSE", contents)
+        );
+        assert_eq!(vec!["İstanbul"], parser::search_case_insensitive("istanbul", contents));
+        assert_eq!(vec!["ΣΊΣΥΦΟΣ"], parser::search_case_insensitive("σίσυφος", contents));
+
+        let matcher = matcher::Matcher::ignore_case("ss");
+        let matches = parser::find_matches(&matcher, contents);
+        assert_eq!("ß", matches[0].matched());
+
+        // both halves of the "ss" that "ß" folds to are the one hit, not two
+        let matcher = matcher::Matcher::ignore_case("s");
+        assert_eq!(vec![0..1, 4..6], matcher.find_all("Straße").into_iter().map(|hit| hit.span).collect::<Vec<_>>());
+        assert_eq!(
+            "\x1b[1;31mS\x1b[0mtra\x1b[1;31mß\x1b[0me\n",
+            output(&["minigrep", "-i", "--color=always", "s", "s.txt"], "Straße\n")
+        );
+        assert_eq!("XtraXe\n", output(&["minigrep", "-i", "--replace", "X", "s", "s.txt"], "Straße\n"));
+        let json = output(&["minigrep", "-i", "--json", "s", "s.txt"], "Straße\n");
+        assert_eq!(2, json.matches("\"match\":").count());
+    }
+
+    #[test]
+    fn multiple_patterns() {
+        let contents = "\
+Rust:
+safe, fast, productive.
+Pick three.
+Trust me.";
+
+        let literals = matcher::Matcher::with_patterns(&["three", "fast", "RUST"], true, false).unwrap();
+        let matches = parser::find_matches(&literals, contents);
+        let found: Vec<_> = matches.iter().map(|m| (m.line.line_number, m.hit.pattern, m.matched())).collect();
+        assert_eq!(vec![(1, 2, "Rust"), (2, 1, "fast"), (3, 0, "three"), (4, 2, "rust")], found);
+
+        let regexes = matcher::Matcher::with_patterns(&[r"^(P)ick", r"(s)(a)fe", r"me\.$"], false, true).unwrap();
+        let hits: Vec<_> = contents.lines().filter_map(|line| regexes.find(line)).map(|hit| hit.pattern).collect();
+        assert_eq!(vec![1, 0, 2], hits);
+
+        // each pattern may name its groups as it likes, even the same as another one
+        let named = [r"a(?P<n>b)", r"c(?<n>(?P<m>d))", r"\(?P<n>e"];
+        let regexes = matcher::Matcher::with_patterns(&named, false, true).unwrap();
+        let hits: Vec<_> = ["xab", "cd", "(?P<n>e"].iter().filter_map(|line| regexes.find(line)).collect();
+        let found: Vec<_> = hits.iter().map(|hit| (hit.pattern, hit.span.clone())).collect();
+        assert_eq!(vec![(0, 1..3), (1, 0..2), (2, 2..7)], found);
+    }
+
+    #[test]
+    fn patterns_replace_query() {
+        let config = build(&["minigrep", "-e", "Rust", "-e", "Pick", "poem.txt"]).unwrap();
+
+        assert_eq!("poem.txt", config.file_path);
+        assert_eq!(vec!["Rust", "Pick"], config.patterns().unwrap());
+    }
+
+    #[test]
+    fn whole_word_and_line() {
+        let contents = "\
+address
+padding add
+add
+Straße_2 STRASSE";
+
+        let word = matcher::Matcher::new("add", false, false).unwrap().bounded(matcher::Boundary::Word);
+        let found: Vec<_> = parser::find_matches(&word, contents).iter().map(|m| (m.line.line_number, m.hit.span.clone())).collect();
+        assert_eq!(vec![(2, 8..11), (3, 0..3)], found);
+
+        let line = matcher::Matcher::new("add", false, false).unwrap().bounded(matcher::Boundary::Line);
+        assert_eq!(3, parser::find_matches(&line, contents)[0].line.line_number);
+
+        // '_' and digits are word characters, and case folding still applies
+        let folded = matcher::Matcher::ignore_case("strasse").bounded(matcher::Boundary::Word);
+        assert_eq!("STRASSE", parser::find_matches(&folded, contents)[0].matched());
+
+        // a hit that fits is found even when a shorter or earlier one doesn't
+        let whole = |patterns: &[&str], regex: bool, boundary: matcher::Boundary| {
+            let matcher = matcher::Matcher::with_patterns(patterns, false, regex).unwrap().bounded(boundary);
+            matcher.find("ab").map(|hit| hit.span)
+        };
+        assert_eq!(Some(0..2), whole(&["a|ab"], true, matcher::Boundary::Line));
+        assert_eq!(Some(0..2), whole(&["a|ab"], true, matcher::Boundary::Word));
+        assert_eq!(Some(0..2), whole(&["a", "ab"], false, matcher::Boundary::Line));
+        assert_eq!(Some(0..2), whole(&["a", "ab"], false, matcher::Boundary::Word));
+        assert_eq!(Some(0..2), whole(&["a", "ab"], true, matcher::Boundary::Line));
+        let overlapping = matcher::Matcher::new("a-a", false, false).unwrap().bounded(matcher::Boundary::Word);
+        assert_eq!(vec![3..6], overlapping.find_all("xa-a-a").into_iter().map(|hit| hit.span).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn replace_and_diff() {
+        let matcher = matcher::Matcher::new("nobody", false, false).unwrap();
+        let poem = std::fs::read_to_string("poem.txt").unwrap();
+
+        let lines = replace::replace_lines(&matcher, &poem, "somebody");
+        assert_eq!("Are you somebody, too?\n", lines[1].1);
+
+        assert_eq!("\
+--- a/poem.txt
++++ b/poem.txt
+@@ -1,5 +1,5 @@
+-I'm nobody! Who are you?
+-Are you nobody, too?
++I'm somebody! Who are you?
++Are you somebody, too?
+ Then there's a pair of us - don't tell!
+ They'd banish us, you know.
+ 
+", replace::unified_diff("poem.txt", &lines));
+    }
+
+    #[test]
+    fn replace_in_place() {
+        let dir = TempDir::new("replace");
+        let path = dir.join("sum.txt");
+        std::fs::write(&path, "add\r\naddress\nno newline add").unwrap();
+
+        let args = ["minigrep", "--no-config", "-w", "--replace", "sum", "--in-place", "add", path.to_str().unwrap()];
+        let config = parser::Config::build(args.iter().map(|arg| arg.to_string())).unwrap();
+        let mut out = Vec::new();
+        assert!(parser::Config::run_to(config, &mut out).unwrap().matched);
+        assert!(out.is_empty());
+        let rewritten = std::fs::read_to_string(&path).unwrap();
+
+        assert_eq!("sum\r\naddress\nno newline sum", rewritten);
+        assert_eq!(
+            args::UsageError::Conflict { flag: "--in-place".to_string(), with: "--invert-match".to_string() },
+            build(&["minigrep", "-v", "--replace", "sum", "--in-place", "add", "poem.txt"]).unwrap_err()
+        );
+    }
+
+    #[test]
+    fn binary_and_encodings() {
+        let config = build(&["minigrep", "café", "-"]).unwrap();
+        let search = |config: &parser::Config, data: &[u8]| {
+            let mut out = Vec::new();
+            let reader = decode::reader(data, 64);
+            let searcher = config.searcher().unwrap();
+            let summary = parser::search_reader(config, &searcher, None, reader, &mut out).unwrap();
+            (summary, String::from_utf8(out).unwrap())
+        };
+
+        // Latin-1 bytes that aren't valid UTF-8 still match
+        let (summary, out) = search(&config, b"un caf\xe9 noir\n");
+        assert_eq!((1, "un café noir\n"), (summary.count, out.as_str()));
+
+        // UTF-16LE with a byte order mark is decoded before searching
+        let utf16: Vec<u8> = [0xfeff].into_iter().chain("le café\n".encode_utf16())
+            .flat_map(|unit: u16| unit.to_le_bytes()).collect();
+        let (summary, out) = search(&config, &utf16);
+        assert_eq!((false, "le café\n"), (summary.binary, out.as_str()));
+
+        // a NUL byte makes it binary: nothing is printed, only whether it matched
+        let (summary, out) = search(&config, b"\x00\x01caf\xc3\xa9\n");
+        assert_eq!(searcher::FileSummary { count: 1, binary: true, matches: 0, bytes: 8 }, summary);
+        assert!(out.is_empty());
+
+        let skip = build(&["minigrep", "-I", "café", "-"]).unwrap();
+        assert_eq!(0, search(&skip, b"\x00\x01caf\xc3\xa9\n").0.count);
+        let text = build(&["minigrep", "--text", "café", "-"]).unwrap();
+        assert_eq!("\0\u{1}café\n", search(&text, b"\x00\x01caf\xc3\xa9\n").1);
+    }
+
+    #[test]
+    fn compressed_input() {
+        use std::io::{Read, Write};
+
+        let poem = std::fs::read("poem.txt").unwrap();
+        let mut gzip = flate2::write::GzEncoder::new(Vec::new(), flate2::Compression::fast());
+        gzip.write_all(&poem).unwrap();
+        let mut bzip2 = bzip2::write::BzEncoder::new(Vec::new(), bzip2::Compression::fast());
+        bzip2.write_all(&poem).unwrap();
+        let mut xz = xz2::write::XzEncoder::new(Vec::new(), 1);
+        xz.write_all(&poem).unwrap();
+        let archives = [
+            gzip.finish().unwrap(),
+            zstd::encode_all(poem.as_slice(), 1).unwrap(),
+            bzip2.finish().unwrap(),
+            xz.finish().unwrap(),
+        ];
+
+        for archive in archives {
+            let mut contents = Vec::new();
+            compress::reader(archive.as_slice(), None).unwrap().read_to_end(&mut contents).unwrap();
+            assert_eq!(poem, contents);
+        }
+
+        // plain text is passed through untouched
+        let mut contents = Vec::new();
+        compress::reader(poem.as_slice(), None).unwrap().read_to_end(&mut contents).unwrap();
+        assert_eq!(poem, contents);
+        assert_eq!(Some(compress::Format::Zstd), compress::Format::from_extension(std::path::Path::new("app.log.zst")));
+    }
+
+    #[test]
+    fn searcher_feeds_sink() {
+        // collects (line number, is match, line)
+        struct Collect(Vec<(usize, bool, String)>);
+
+        impl searcher::Sink for Collect {
+            fn matched(&mut self, _: &searcher::Searcher, line: &searcher::SinkLine) -> std::io::Result<bool> {
+                self.0.push((line.line_number, true, line.line.to_string()));
+                // stop after the second match
+                Ok(self.0.iter().filter(|(_, is_match, _)| *is_match).count() < 2)
+            }
+
+            fn context(&mut self, _: &searcher::Searcher, line: &searcher::SinkLine) -> std::io::Result<bool> {
+                self.0.push((line.line_number, false, line.line.to_string()));
+                Ok(true)
+            }
+        }
+
+        let searcher = searcher::SearcherBuilder::new()
+            .pattern("HOW")
+            .pattern("banish")
+            .ignore_case(true)
+            .before_context(1)
+            .build()
+            .unwrap();
+        let mut sink = Collect(Vec::new());
+        let summary = searcher.search_str(&std::fs::read_to_string("poem.txt").unwrap(), &mut sink).unwrap();
+
+        assert_eq!(2, summary.count);
+        assert_eq!(vec![
+            (3, false, "Then there's a pair of us - don't tell!".to_string()),
+            (4, true, "They'd banish us, you know.".to_string()),
+            (5, false, "".to_string()),
+            (6, true, "How dreary to be somebody!".to_string()),
+        ], sink.0);
+    }
+
+    #[test]
+    fn fuzzy_ranked_by_distance() {
+        let contents = "\
+connection refused
+conection refused
+conecton reset
+no connection";
+
+        assert_eq!(vec![(0, "connection refused"), (0, "no connection"), (1, "conection refused")],
+            parser::search_fuzzy("connection", contents, 1)
+        );
+        assert_eq!((2, "conecton reset"), parser::search_fuzzy("connection", contents, 2)[3]);
+
+        // the hit covers the closest part of the line, not the whole line
+        let matcher = matcher::Matcher::fuzzy(&["RECIEVED"], true, 2);
+        let matches = parser::find_matches(&matcher, "packet received ok");
+        assert_eq!("received", matches[0].matched());
+        assert_eq!(Some(2), matcher.distance("packet received ok"));
+
+        // minigrep itself prints each file's lines closest first too, and --json says how close
+        assert_eq!(
+            "1:connection refused\n4:no connection\n2:conection refused\n3:conecton reset\n",
+            output(&["minigrep", "-n", "--fuzzy", "2", "connection", "log.txt"], contents)
+        );
+        let json = output(&["minigrep", "--json", "--fuzzy", "2", "connection", "log.txt"], contents);
+        let distances: Vec<&str> = json.lines().filter_map(|line| line.split("\"distance\":").nth(1)).collect();
+        assert_eq!(vec!["0}", "0}", "1}", "2}"], distances);
+    }
+
+    #[test]
+    fn config_file_precedence() {
+        let dir = TempDir::new("config");
+        let path = dir.join("config.toml");
+        std::fs::write(&path, "ignore-case = true\ncontext = 2\nexclude = [\"target\", \"*.lock\"]\nhidden = false\n").unwrap();
+        let defaults = config_file::load(&path).unwrap();
+        std::fs::write(&path, "replace = \"x\"").unwrap();
+        let not_a_default = config_file::load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+
+        let cli = ["-s", "-A", "1", "--exclude", "*.min.js", "query", "src"].map(String::from);
+        let config = parser::Config::with_defaults(defaults, true, args::parse(cli.into_iter()).unwrap()).unwrap();
+
+        assert!(!config.ignore_case);
+        assert_eq!((2, 1), (config.before_context, config.after_context));
+        assert_eq!(vec!["target", "*.lock", "*.min.js"], config.exclude);
+        assert!(!config.hidden);
+        assert!(matches!(not_a_default, args::UsageError::ConfigFile { source: None, .. }));
+
+        // what went wrong underneath is kept as the source
+        let missing = error::MinigrepError::Usage(config_file::load(&path).unwrap_err());
+        let expected = format!("{}: can't read: No such file or directory (os error 2)", path.display());
+        assert_eq!(expected, error::chain(&missing));
+        std::fs::write(&path, "context = ").unwrap();
+        let invalid = config_file::load(&path).unwrap_err();
+        assert!(matches!(invalid, args::UsageError::ConfigFile { source: Some(_), .. }));
+    }
+
+    #[test]
+    fn errors_carry_context() {
+        let missing = build(&["minigrep", "query", "no_such_file.txt"]).unwrap();
+        let err = parser::Config::run_to(missing, std::io::sink()).unwrap_err();
+        assert!(matches!(&err, error::MinigrepError::Io { path, .. } if path.ends_with("no_such_file.txt")));
+        assert!(error::chain(&err).starts_with("no_such_file.txt: "));
+        assert_eq!(error::EXIT_IO, err.exit_code());
+
+        let invalid = build(&["minigrep", "-E", "-e", "ok", "-e", "(unclosed", "poem.txt"]).unwrap();
+        let err = invalid.searcher().err().unwrap();
+        assert!(matches!(&err, error::MinigrepError::Pattern { pattern, .. } if pattern == "(unclosed"));
+        assert!(std::error::Error::source(&err).is_some());
+        assert_eq!(error::EXIT_PATTERN, err.exit_code());
+    }
+
+    #[test]
+    fn follow_truncation_and_rotation() {
+        use std::io::{BufRead, Write};
+        use std::time::Duration;
+
+        let dir = TempDir::new("follow");
+        let path = dir.join("app.log");
+        std::fs::write(&path, "before following\n").unwrap();
+
+        let (changes, changed) = std::sync::mpsc::channel();
+        let notice = move |_: &std::path::Path, change| changes.send(change).unwrap();
+        let follow = follow::Follow::open(&path, Duration::from_millis(5), notice).unwrap();
+        let writer = {
+            let path = path.clone();
+            std::thread::spawn(move || {
+                let pause = || std::thread::sleep(Duration::from_millis(100));
+                let mut log = std::fs::OpenOptions::new().append(true).open(&path).unwrap();
+                log.write_all(b"appended\n").unwrap();
+                pause();
+                std::fs::write(&path, "truncated\n").unwrap();
+                pause();
+                std::fs::rename(&path, path.with_extension("log.1")).unwrap();
+                std::fs::write(&path, "rotated\n").unwrap();
+            })
+        };
+
+        let lines: Vec<String> = std::io::BufReader::new(follow).lines().take(3).map(Result::unwrap).collect();
+        writer.join().unwrap();
+
+        assert_eq!(vec!["appended", "truncated", "rotated"], lines);
+        let changes: Vec<follow::Change> = changed.try_iter().collect();
+        assert_eq!(vec![follow::Change::Truncated, follow::Change::Replaced], changes);
+    }
+
+    #[test]
+    fn index_prunes_and_updates() {
+        let root = TempDir::new("index");
+        std::fs::write(root.join("a.txt"), "Connection refused\n").unwrap();
+        std::fs::write(root.join("b.txt"), "all good\n").unwrap();
+        let files = vec![root.join("a.txt"), root.join("b.txt")];
+
+        let config = build(&["minigrep", "--index", "-i", "REFUSED", "."]).unwrap();
+        let candidates = index::candidates(&root, &config, &config.patterns().unwrap(), files.clone());
+        assert_eq!(vec![root.join("a.txt")], candidates);
+
+        // a changed file is indexed again on the next run
+        std::fs::write(root.join("b.txt"), "refused as well, and longer now\n").unwrap();
+        let candidates = index::candidates(&root, &config, &config.patterns().unwrap(), files.clone());
+        assert_eq!(files, candidates);
+
+        // and the index on disk is what the last run left behind
+        let mut expected = index::Index::default();
+        expected.update(&root, &files);
+        let saved = index::Index::load(&root.join(index::INDEX_FILE)).unwrap();
+        assert_eq!(expected, saved);
+
+        // an index that can't be saved still gets used for this run
+        std::fs::remove_file(root.join(index::INDEX_FILE)).unwrap();
+        std::fs::create_dir(root.join(index::INDEX_FILE)).unwrap();
+        std::fs::write(root.join("b.txt"), "all good again\n").unwrap();
+        let candidates = index::candidates(&root, &config, &config.patterns().unwrap(), files.clone());
+        assert_eq!(vec![root.join("a.txt")], candidates);
+    }
+
+    #[test]
+    fn multiline_and_records() {
+        let log = "INFO start\nERROR boom\n  at foo.rs:1\nINFO done\n";
+
+        // a hit spanning lines selects every line it touches, and only with -U
+        assert_eq!("2:ERROR boom\n3:  at foo.rs:1\n", output(&["minigrep", "-n", "-U", "boom\n  at", "-"], log));
+        assert_eq!("", output(&["minigrep", "-n", "boom\n  at", "-"], log));
+
+        // blank lines are lines like any other, inside a hit or around one
+        let blank = "a\n\nb\nc\n";
+        assert_eq!("1:a\n2:\n3:b\n", output(&["minigrep", "-n", "-U", "a\n\nb", "-"], blank));
+        assert_eq!("1:a\n2:\n4:c\n", output(&["minigrep", "-n", "-U", "-v", "b", "-"], blank));
+        assert_eq!("2-\n3:b\n", output(&["minigrep", "-n", "-U", "-B1", "b", "-"], blank));
+
+        // paragraphs are numbered by their first line and printed apart
+        let text = "one\ntwo\n\n\nthree needle\nfour\n\nfive needle\n";
+        assert_eq!(
+            "5:three needle\nfour\n\n8:five needle\n",
+            output(&["minigrep", "-n", "--paragraph", "needle", "-"], text)
+        );
+        assert_eq!("5:three needle\nfour\n", output(&["minigrep", "-n", "--paragraph", "needle\nfour", "-"], text));
+
+        assert_eq!(
+            "1:a\n%%\n3:c\n",
+            output(&["minigrep", "-n", "-v", "--record-separator", "%%", "b", "-"], "a%%\nb%%\nc\n")
+        );
+
+        assert!(build(&["minigrep", "--record-separator", "", "x", "poem.txt"]).is_err());
+        assert!(build(&["minigrep", "-U", "--follow", "x", "poem.txt"]).is_err());
+    }
+
+    #[test]
+    fn field_restricted_search() {
+        let csv = "time,level,message\n1,ERROR,\"disk, full\"\n2,INFO,ERROR mentioned\n";
+        assert_eq!(vec!["1,ERROR,\"disk, full\""], parser::search_field("ERROR", "level", csv));
+        assert_eq!(vec!["1,ERROR,\"disk, full\""], parser::search_field("disk, full", "message", csv));
+        assert_eq!(vec!["2,INFO,ERROR mentioned"], parser::search_field("INFO", "2", csv));
+        assert!(parser::search_field("ERROR", "missing", csv).is_empty());
+
+        let jsonl = "{\"level\":\"error\",\"ctx\":{\"user\":\"bob\"}}\n{\"level\":\"info\",\"msg\":\"error\"}\n{\"level\":\"\\u0065rror\"}\n";
+        let lines: Vec<&str> = jsonl.lines().collect();
+        assert_eq!(vec![lines[0], lines[2]], parser::search_field("error", "level", jsonl));
+        assert_eq!(vec![lines[0]], parser::search_field("bob", "ctx.user", jsonl));
+
+        // the whole record is printed, but only hits inside the field are highlighted
+        assert_eq!(
+            "{\"level\":\"info\",\"msg\":\"\x1b[1;31merror\x1b[0m\"}\n",
+            output(&["minigrep", "--color=always", "--field", "msg", "error", "log.jsonl"], jsonl)
+        );
+
+        assert!(build(&["minigrep", "--field", "level", "-U", "x", "log.jsonl"]).is_err());
+
+        // a quoted field is cut off at the end of the record, which is usually the line
+        assert_eq!("", output(&["minigrep", "--field", "msg", "full", "-"], "id,msg\n1,\"disk\nfull\"\n"));
+        assert_eq!(
+            "1,\"disk\nfull\"\n",
+            output(&["minigrep", "--field", "msg", "--record-separator", ";", "full", "-"], "id,msg;1,\"disk\nfull\";2,ok")
+        );
+    }
+
+    #[test]
+    fn stats_report() {
+        let root = TempDir::new("stats");
+        std::fs::write(root.join("a.txt"), "rust and rust\nno\nrust\n").unwrap();
+        std::fs::write(root.join("b.txt"), "nothing here\n").unwrap();
+        std::fs::write(root.join("c.bin"), b"rust\x00\n").unwrap();
+
+        let args = ["minigrep", "--no-config", "--stats", "-c", "-I", "--sort", "rust", root.to_str().unwrap()];
+        let config = parser::Config::build(args.iter().map(|arg| arg.to_string())).unwrap();
+        let mut out = Vec::new();
+        let stats = parser::Config::run_to(config, &mut out).unwrap().stats;
+
+        let expected = stats::Stats {
+            files_searched: 2,
+            files_skipped: 1,
+            files_matched: 1,
+            matched_lines: 2,
+            matches: 3,
+            bytes_searched: 35,
+            elapsed: stats.elapsed,
+        };
+        assert_eq!(expected, stats);
+        let path = |name: &str| root.join(name).display().to_string();
+        let report = format!(
+            "{}:2\n{}:0\n{}:0\n\n2 files searched, 1 skipped\n1 files matched\n2 matched lines\n3 matches\n35 bytes searched\n{:.6} seconds\n",
+            path("a.txt"),
+            path("b.txt"),
+            path("c.bin"),
+            stats.elapsed.as_secs_f64()
+        );
+        assert_eq!(report, String::from_utf8(out).unwrap());
+        assert!(build(&["minigrep", "--stats", "--replace", "x", "--in-place", "y", "poem.txt"]).is_err());
+    }
+
+    #[test]
+    fn large_output_is_not_interleaved() {
+        let root = TempDir::new("large");
+        // far more output than is held on to for one file, next to lots of small files
+        let big: String = (0..40_000).map(|n| format!("rust line {n:05} of the big file\n")).collect();
+        std::fs::write(root.join("big.txt"), &big).unwrap();
+        for n in 0..20 {
+            std::fs::write(root.join(format!("small{n}.txt")), "rust\nno\nrust\n").unwrap();
+        }
+
+        let args = ["minigrep", "--no-config", "-j", "4", "rust", root.to_str().unwrap()];
+        let config = parser::Config::build(args.iter().map(|arg| arg.to_string())).unwrap();
+        let mut out = Vec::new();
+        parser::Config::run_to(config, &mut out).unwrap();
+
+        let out = String::from_utf8(out).unwrap();
+        let mut paths: Vec<&str> = out.lines().map(|line| line.split_once(':').unwrap().0).collect();
+        assert_eq!(40_000 + 20 * 2, paths.len());
+        paths.dedup();
+        assert_eq!(21, paths.len());
+    }
 }
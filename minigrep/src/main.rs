@@ -1,18 +1,45 @@
 use std::env;
+use std::io;
 use std::process;
-use minigrep::parser::Config;
+use minigrep::args::{UsageError, USAGE};
+use minigrep::error::{self, MinigrepError};
+use minigrep::parser::{Config, OutputMode, Outcome};
 
 // eprintln!() will print to standard error
 // println!() will print to standard output
 
+// same exit statuses as grep for a match and no match, so scripts can tell them apart;
+// errors get the ones in `minigrep::error`
+const EXIT_MATCH: i32 = 0;
+const EXIT_NO_MATCH: i32 = 1;
+
 fn main() {
     let config = Config::build(env::args()).unwrap_or_else(|err| {
-        eprintln!("Problem parsing arguments: {err}");
-        process::exit(1);
+        if err == UsageError::Help {
+            print!("{USAGE}");
+            process::exit(EXIT_MATCH);
+        }
+        let err = MinigrepError::Usage(err);
+        eprintln!("Problem parsing arguments: {}", error::chain(&err));
+        eprintln!("Try 'minigrep --help' for more information.");
+        process::exit(err.exit_code());
     });
 
-    if let Err(e) = Config::run(config) {
-        eprintln!("Application error: {e}");
-        process::exit(1);
+    // like grep, -q only cares whether something matched, even if some files failed
+    let quiet = config.output == OutputMode::Quiet;
+    match Config::run(config) {
+        Ok(Outcome { matched: true, .. }) if quiet => process::exit(EXIT_MATCH),
+        // the files that failed were already reported as they came up
+        Ok(Outcome { errors, .. }) if !errors.is_empty() => process::exit(errors[0].exit_code()),
+        Ok(Outcome { matched: true, .. }) => process::exit(EXIT_MATCH),
+        Ok(Outcome { matched: false, .. }) => process::exit(EXIT_NO_MATCH),
+        // whoever was reading the output (`minigrep ... | head`) has seen enough
+        Err(MinigrepError::Output(err)) if err.kind() == io::ErrorKind::BrokenPipe => {
+            process::exit(EXIT_MATCH)
+        }
+        Err(e) => {
+            eprintln!("Application error: {}", error::chain(&e));
+            process::exit(e.exit_code());
+        }
     }
 }
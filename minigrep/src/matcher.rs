@@ -0,0 +1,500 @@
+use std::ops::Range;
+
+use aho_corasick::{AhoCorasick, MatchKind};
+use caseless::Caseless;
+use regex::{Regex, RegexBuilder};
+use regex_syntax::ast::{self, Ast};
+use regex_syntax::is_word_character;
+
+use crate::error::MinigrepError;
+use crate::fuzzy;
+
+// Decides whether a single line matches the query and, if so, where.
+//
+// Every search mode boils down to "find the first hit in this line", so the rest of
+// the crate only ever talks to a Matcher and never cares how the query was given.
+pub enum Matcher {
+    Literal(String),
+    // the query is stored already case folded
+    IgnoreCase(String),
+    // several plain patterns, all looked for in a single pass over the line; with
+    // ignore_case the automaton is built from the case folded patterns, which are kept
+    // too for `all_hits`
+    Literals {
+        automaton: AhoCorasick,
+        patterns: Vec<String>,
+        ignore_case: bool,
+    },
+    // one or more regexes joined into a single alternation; `groups[i]` is the capture
+    // group wrapping pattern i, and is empty when there's only one pattern
+    Regex {
+        re: Regex,
+        groups: Vec<usize>,
+        ignore_case: bool,
+    },
+    // --fuzzy: hits within `max_distance` edits of any of the patterns
+    Fuzzy {
+        patterns: Vec<Vec<char>>,
+        ignore_case: bool,
+        max_distance: usize,
+    },
+    // any of the above, but only hits that line up with word or line boundaries count
+    Bounded {
+        inner: Box<Matcher>,
+        boundary: Boundary,
+    },
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Boundary {
+    // a hit can be anywhere
+    #[default]
+    Any,
+    // -w: a hit can't have a word character right before or after it
+    Word,
+    // -x: a hit has to cover the whole line
+    Line,
+}
+
+impl Boundary {
+    fn accepts(self, line: &str, span: &Range<usize>) -> bool {
+        match self {
+            Boundary::Any => true,
+            // word characters are the same Unicode-aware set as the regex \w class:
+            // letters, marks, digits and connector punctuation such as '_'
+            Boundary::Word => {
+                let before = line[..span.start].chars().next_back();
+                let after = line[span.end..].chars().next();
+                !span.is_empty()
+                    && !before.is_some_and(is_word_character)
+                    && !after.is_some_and(is_word_character)
+            }
+            Boundary::Line => *span == (0..line.len()),
+        }
+    }
+}
+
+// One hit within a line: which pattern matched (its index in the order the patterns
+// were given) and the byte range it covers.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Hit {
+    pub pattern: usize,
+    pub span: Range<usize>,
+}
+
+impl Matcher {
+    pub fn new(query: &str, ignore_case: bool, regex: bool) -> Result<Matcher, regex::Error> {
+        let matcher = if regex {
+            let re = RegexBuilder::new(query)
+                .case_insensitive(ignore_case)
+                .build()?;
+            Matcher::Regex { re, groups: Vec::new(), ignore_case }
+        } else if ignore_case {
+            Matcher::ignore_case(query)
+        } else {
+            Matcher::Literal(query.to_string())
+        };
+
+        Ok(matcher)
+    }
+
+    // A line matches if any of the patterns does (-e/-f). No patterns at all never matches.
+    pub fn with_patterns<S: AsRef<str>>(
+        patterns: &[S],
+        ignore_case: bool,
+        regex: bool,
+    ) -> Result<Matcher, MinigrepError> {
+        let invalid = |pattern: &str, source| MinigrepError::Pattern {
+            pattern: pattern.to_string(),
+            source,
+        };
+        if patterns.len() == 1 {
+            let pattern = patterns[0].as_ref();
+            return Matcher::new(pattern, ignore_case, regex).map_err(|err| invalid(pattern, err.into()));
+        }
+
+        if regex && !patterns.is_empty() {
+            // each pattern is compiled on its own first, both to report exactly which one
+            // is invalid and to count its capture groups
+            let mut alternatives = Vec::with_capacity(patterns.len());
+            let mut groups = Vec::with_capacity(patterns.len());
+            let mut next_group = 1;
+            for pattern in patterns {
+                let pattern = pattern.as_ref();
+                let captures = Regex::new(pattern)
+                    .map_err(|err| invalid(pattern, err.into()))?
+                    .captures_len();
+                // two patterns may well name a group the same, which one regex can't have
+                alternatives.push(format!("({})", unnamed_groups(pattern)));
+                groups.push(next_group);
+                next_group += captures;
+            }
+            let alternation = alternatives.join("|");
+            let re = RegexBuilder::new(&alternation)
+                .case_insensitive(ignore_case)
+                .build()
+                .map_err(|err| invalid(&alternation, err.into()))?;
+            return Ok(Matcher::Regex { re, groups, ignore_case });
+        }
+
+        let patterns: Vec<String> = patterns
+            .iter()
+            .map(|pattern| match ignore_case {
+                true => case_fold(pattern.as_ref()),
+                false => pattern.as_ref().to_string(),
+            })
+            .collect();
+        let automaton = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .build(&patterns)
+            .map_err(|err| invalid(&patterns.join("|"), err.into()))?;
+
+        Ok(Matcher::Literals { automaton, patterns, ignore_case })
+    }
+
+    // Approximate matching: a line matches if some part of it is at most `max_distance`
+    // edits away from one of the patterns. With ignore_case characters are compared
+    // lowercased, one for one, rather than fully case folded.
+    pub fn fuzzy<S: AsRef<str>>(patterns: &[S], ignore_case: bool, max_distance: usize) -> Matcher {
+        Matcher::Fuzzy {
+            patterns: patterns.iter().map(|pattern| pattern.as_ref().chars().collect()).collect(),
+            ignore_case,
+            max_distance,
+        }
+    }
+
+    pub fn ignore_case(query: &str) -> Matcher {
+        Matcher::IgnoreCase(case_fold(query))
+    }
+
+    // Restricts this matcher to whole words (-w) or whole lines (-x). A regex gets the
+    // boundary compiled into it, so the regex engine itself looks for a hit that fits;
+    // anything else picks from all of its hits (see `all_hits`).
+    pub fn bounded(self, boundary: Boundary) -> Matcher {
+        let (pattern, ignore_case) = match (&self, boundary) {
+            (_, Boundary::Any) => return self,
+            (Matcher::Regex { re, ignore_case, .. }, _) => (re.as_str(), *ignore_case),
+            _ => {
+                return Matcher::Bounded {
+                    inner: Box::new(self),
+                    boundary,
+                }
+            }
+        };
+        // the half boundaries only look at one side each: no word character before the
+        // hit, and none after it, which is what -w asks for
+        let bounded = match boundary {
+            Boundary::Line => format!("^(?:{pattern})$"),
+            _ => format!(r"\b{{start-half}}(?:{pattern})\b{{end-half}}"),
+        };
+        // the groups keep their numbers, since (?:) doesn't capture
+        let groups = match &self {
+            Matcher::Regex { groups, .. } => groups.clone(),
+            _ => Vec::new(),
+        };
+        match RegexBuilder::new(&bounded).case_insensitive(ignore_case).build() {
+            Ok(re) => Matcher::Regex { re, groups, ignore_case },
+            // only a regex close to the size limit could fail to grow; checking its hits
+            // afterwards is the next best thing
+            Err(_) => Matcher::Bounded {
+                inner: Box::new(self),
+                boundary,
+            },
+        }
+    }
+
+    pub fn is_match(&self, line: &str) -> bool {
+        match self {
+            Matcher::Literals { automaton, ignore_case: false, .. } => automaton.is_match(line),
+            Matcher::Regex { re, .. } => re.is_match(line),
+            _ => self.find(line).is_some(),
+        }
+    }
+
+    // The first hit within `line`.
+    pub fn find(&self, line: &str) -> Option<Hit> {
+        match self {
+            Matcher::Literal(query) => line.find(query.as_str()).map(|start| Hit {
+                pattern: 0,
+                span: start..start + query.len(),
+            }),
+            Matcher::IgnoreCase(query) => {
+                let folded = Folded::new(line);
+                let start = folded.text.find(query.as_str())?;
+                Some(Hit {
+                    pattern: 0,
+                    span: folded.span(line, start..start + query.len()),
+                })
+            }
+            Matcher::Literals { automaton, ignore_case: false, .. } => {
+                automaton.find(line).map(|m| Hit {
+                    pattern: m.pattern().as_usize(),
+                    span: m.range(),
+                })
+            }
+            Matcher::Literals { automaton, ignore_case: true, .. } => {
+                let folded = Folded::new(line);
+                automaton.find(&folded.text).map(|m| Hit {
+                    pattern: m.pattern().as_usize(),
+                    span: folded.span(line, m.range()),
+                })
+            }
+            Matcher::Regex { re, groups, .. } if groups.is_empty() => re.find(line).map(|m| Hit {
+                pattern: 0,
+                span: m.range(),
+            }),
+            Matcher::Regex { re, groups, .. } => re
+                .captures(line)
+                .map(|caps| regex_hit(&caps, groups)),
+            Matcher::Fuzzy { .. } => self.find_fuzzy(line).map(|(_, hit)| hit),
+            Matcher::Bounded { .. } => self.find_all(line).into_iter().next(),
+        }
+    }
+
+    // How many edits the closest hit in `line` is away from its pattern, for ranking
+    // fuzzy matches; 0 for any exact hit.
+    pub fn distance(&self, line: &str) -> Option<usize> {
+        match self {
+            Matcher::Fuzzy { .. } => self.find_fuzzy(line).map(|(distance, _)| distance),
+            _ => self.find(line).map(|_| 0),
+        }
+    }
+
+    // The closest fuzzy hit in `line` over all the patterns, the lowest pattern index
+    // winning ties, along with its distance.
+    fn find_fuzzy(&self, line: &str) -> Option<(usize, Hit)> {
+        let Matcher::Fuzzy { patterns, ignore_case, max_distance } = self else {
+            return None;
+        };
+        patterns
+            .iter()
+            .enumerate()
+            .filter_map(|(pattern, chars)| {
+                let (distance, span) = fuzzy::find(chars, line, *ignore_case, *max_distance)?;
+                Some((distance, Hit { pattern, span }))
+            })
+            .min_by_key(|(distance, hit)| (*distance, hit.span.start))
+    }
+
+    // Every non-overlapping hit within `line`, left to right.
+    pub fn find_all(&self, line: &str) -> Vec<Hit> {
+        match self {
+            Matcher::Literal(query) => line
+                .match_indices(query.as_str())
+                .map(|(start, hit)| Hit {
+                    pattern: 0,
+                    span: start..start + hit.len(),
+                })
+                .collect(),
+            Matcher::IgnoreCase(query) => {
+                let folded = Folded::new(line);
+                let hits = folded.text.match_indices(query.as_str()).map(|(start, hit)| Hit {
+                    pattern: 0,
+                    span: folded.span(line, start..start + hit.len()),
+                });
+                without_overlaps(hits)
+            }
+            Matcher::Literals { automaton, ignore_case: false, .. } => automaton
+                .find_iter(line)
+                .map(|m| Hit {
+                    pattern: m.pattern().as_usize(),
+                    span: m.range(),
+                })
+                .collect(),
+            Matcher::Literals { automaton, ignore_case: true, .. } => {
+                let folded = Folded::new(line);
+                let hits = automaton.find_iter(&folded.text).map(|m| Hit {
+                    pattern: m.pattern().as_usize(),
+                    span: folded.span(line, m.range()),
+                });
+                without_overlaps(hits)
+            }
+            Matcher::Regex { re, groups, .. } if groups.is_empty() => re
+                .find_iter(line)
+                .map(|m| Hit {
+                    pattern: 0,
+                    span: m.range(),
+                })
+                .collect(),
+            Matcher::Regex { re, groups, .. } => re
+                .captures_iter(line)
+                .map(|caps| regex_hit(&caps, groups))
+                .collect(),
+            // each search starts where the last hit ended; an empty hit (a pattern no
+            // longer than the distance allowed) would never move on, so it's the last one
+            Matcher::Fuzzy { .. } => {
+                let mut hits = Vec::new();
+                let mut start = 0;
+                while let Some((_, hit)) = self.find_fuzzy(&line[start..]) {
+                    let span = start + hit.span.start..start + hit.span.end;
+                    start = span.end;
+                    let empty = span.is_empty();
+                    hits.push(Hit { pattern: hit.pattern, span });
+                    if empty || start == line.len() {
+                        break;
+                    }
+                }
+                hits
+            }
+            // a hit that fails the boundary check just doesn't count, and the ones that
+            // pass are taken leftmost first, like any others
+            Matcher::Bounded { inner, boundary } => {
+                let mut hits: Vec<Hit> = inner
+                    .all_hits(line)
+                    .into_iter()
+                    .filter(|hit| boundary.accepts(line, &hit.span))
+                    .collect();
+                hits.sort_by_key(|hit| (hit.span.start, hit.pattern));
+                without_overlaps(hits.into_iter())
+            }
+        }
+    }
+
+    // Every hit of every pattern, overlapping ones too. The usual leftmost-first hits
+    // can hide one that fits a boundary: with -x, "a" in "ab" hides "ab" from "a|ab".
+    fn all_hits(&self, line: &str) -> Vec<Hit> {
+        let (patterns, ignore_case) = match self {
+            Matcher::Literal(query) => (std::slice::from_ref(query), false),
+            Matcher::IgnoreCase(query) => (std::slice::from_ref(query), true),
+            Matcher::Literals { patterns, ignore_case, .. } => (patterns.as_slice(), *ignore_case),
+            _ => return self.find_all(line),
+        };
+
+        let folded = ignore_case.then(|| Folded::new(line));
+        let text = folded.as_ref().map_or(line, |folded| folded.text.as_str());
+        let mut hits = Vec::new();
+        for (pattern, query) in patterns.iter().enumerate() {
+            let mut start = 0;
+            while let Some(found) = text.get(start..).and_then(|rest| rest.find(query.as_str())) {
+                let found = start + found;
+                let span = found..found + query.len();
+                let span = folded.as_ref().map_or(span.clone(), |folded| folded.span(line, span));
+                hits.push(Hit { pattern, span });
+                // on to the next character, so overlapping hits are found too
+                start = found + text[found..].chars().next().map_or(1, char::len_utf8);
+            }
+        }
+        hits
+    }
+}
+
+// Hits found in a case folded line, mapped back onto the original. Two hits within a
+// character that folds to several ("s" twice in the "ss" of "ß") map to the same
+// span, so only the first of any hits that overlap is kept.
+fn without_overlaps(hits: impl Iterator<Item = Hit>) -> Vec<Hit> {
+    let mut kept: Vec<Hit> = Vec::new();
+    for hit in hits {
+        if kept.last().is_none_or(|last| hit.span.start >= last.span.end) {
+            kept.push(hit);
+        }
+    }
+    kept
+}
+
+// `pattern` with its named groups, (?P<name>...) or (?<name>...), made plain numbered
+// ones. Their numbers stay the same, and nothing here looks groups up by name.
+fn unnamed_groups(pattern: &str) -> String {
+    struct Names(Vec<Range<usize>>);
+
+    impl ast::Visitor for Names {
+        type Output = Vec<Range<usize>>;
+        type Err = ();
+
+        fn finish(self) -> Result<Self::Output, ()> {
+            Ok(self.0)
+        }
+
+        fn visit_pre(&mut self, ast: &Ast) -> Result<(), ()> {
+            if let Ast::Group(group) = ast {
+                if let ast::GroupKind::CaptureName { name, .. } = &group.kind {
+                    // from just after the "(" to just after the ">" that ends the name
+                    self.0.push(group.span.start.offset + 1..name.span.end.offset + 1);
+                }
+            }
+            Ok(())
+        }
+    }
+
+    let Ok(parsed) = ast::parse::Parser::new().parse(pattern) else {
+        return pattern.to_string();
+    };
+    let mut unnamed = pattern.to_string();
+    // in the order the groups start, so from the back the earlier ranges stay put
+    for range in ast::visit(&parsed, Names(Vec::new())).unwrap_or_default().into_iter().rev() {
+        unnamed.replace_range(range, "");
+    }
+    unnamed
+}
+
+// Exactly one of the pattern groups takes part in any match of the alternation.
+fn regex_hit(caps: &regex::Captures, groups: &[usize]) -> Hit {
+    let whole = caps.get(0).unwrap();
+    let pattern = groups
+        .iter()
+        .position(|&group| caps.get(group).is_some())
+        .unwrap_or(0);
+
+    Hit {
+        pattern,
+        span: whole.range(),
+    }
+}
+
+// Full Unicode case folding rather than plain lowercasing, so "Straße" matches
+// "STRASSE" and final sigma matches sigma. On top of that, the Turkic capital dotted
+// I folds to a plain "i" (instead of "i" plus a combining dot) so "İstanbul" is found
+// by "istanbul".
+pub fn case_fold(text: &str) -> String {
+    let mut folded = String::with_capacity(text.len());
+    for c in text.chars() {
+        push_folded(c, &mut folded);
+    }
+    folded
+}
+
+fn push_folded(c: char, folded: &mut String) {
+    if c == '\u{130}' {
+        folded.push('i');
+    } else {
+        folded.extend(std::iter::once(c).default_case_fold());
+    }
+}
+
+// A case folded copy of a line. Folding can change the byte length of a character,
+// so hits in the copy are mapped back onto the original through a per-byte offset table.
+struct Folded {
+    text: String,
+    // offsets[i] is where the character behind text byte i starts in the original
+    offsets: Vec<usize>,
+}
+
+impl Folded {
+    fn new(line: &str) -> Folded {
+        let mut text = String::with_capacity(line.len());
+        let mut offsets = Vec::with_capacity(line.len() + 1);
+
+        for (index, c) in line.char_indices() {
+            push_folded(c, &mut text);
+            offsets.resize(text.len(), index);
+        }
+        offsets.push(line.len());
+
+        Folded { text, offsets }
+    }
+
+    fn span(&self, line: &str, hit: Range<usize>) -> Range<usize> {
+        let offsets = &self.offsets;
+        let Range { start, end } = hit;
+        // a hit that ends part way through an expanded character still covers all of it
+        let end = if end > start && end < self.text.len() && offsets[end] == offsets[end - 1] {
+            line[offsets[end]..]
+                .chars()
+                .next()
+                .map_or(line.len(), |c| offsets[end] + c.len_utf8())
+        } else {
+            offsets[end]
+        };
+
+        offsets[start]..end
+    }
+}
@@ -0,0 +1,199 @@
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process;
+
+use crate::decode::{self, BinaryFiles};
+use crate::error::{self, MinigrepError};
+use crate::matcher::Matcher;
+use crate::parser::{Config, Outcome};
+
+// lines of unchanged context around each change in a unified diff, same as diff -u
+const DIFF_CONTEXT: usize = 3;
+
+// `line` with every hit the matcher finds swapped for `replacement`. The replacement is
+// inserted as is; there's no $1-style expansion of regex groups.
+pub fn replace_line(matcher: &Matcher, line: &str, replacement: &str) -> String {
+    let mut replaced = String::with_capacity(line.len());
+    let mut written = 0;
+    for hit in matcher.find_all(line) {
+        replaced.push_str(&line[written..hit.span.start]);
+        replaced.push_str(replacement);
+        written = hit.span.end;
+    }
+    replaced.push_str(&line[written..]);
+    replaced
+}
+
+// Every line of `contents` next to what it turns into, both with the line's own ending
+// ("\n", "\r\n" or none at the end of the file) left untouched.
+pub fn replace_lines<'a>(
+    matcher: &Matcher,
+    contents: &'a str,
+    replacement: &str,
+) -> Vec<(&'a str, String)> {
+    contents
+        .split_inclusive('\n')
+        .map(|line| {
+            let (text, ending) = split_line_ending(line);
+            (line, replace_line(matcher, text, replacement) + ending)
+        })
+        .collect()
+}
+
+// --replace together with --in-place: rewrites every file that changes, or with
+// --dry-run only prints what would change as a unified diff. `Outcome::matched` says
+// whether any file did (or would) change.
+//
+// Binary files are left alone unless --text says otherwise. Files that aren't UTF-8
+// are skipped with an error instead, since writing them back as UTF-8 would change
+// more than the matches.
+pub fn rewrite_files(
+    config: &Config,
+    matcher: &Matcher,
+    files: &[PathBuf],
+    out: &mut impl Write,
+) -> Result<Outcome, MinigrepError> {
+    let replacement = config.replace.as_deref().unwrap_or_default();
+    let mut outcome = Outcome::default();
+
+    for path in files {
+        let old = match read_text(config, path) {
+            Ok(Some(old)) => old,
+            Ok(None) => continue,
+            Err(err) => {
+                error::report(&err);
+                outcome.errors.push(err);
+                continue;
+            }
+        };
+        let lines = replace_lines(matcher, &old, replacement);
+        if lines.iter().all(|(old, new)| old == new) {
+            continue;
+        }
+        outcome.matched = true;
+
+        if config.dry_run {
+            let name = path.display().to_string();
+            out.write_all(unified_diff(&name, &lines).as_bytes())
+                .map_err(MinigrepError::Output)?;
+        } else {
+            let new: String = lines.into_iter().map(|(_, new)| new).collect();
+            if let Err(err) = write_atomically(path, &new) {
+                let err = MinigrepError::io(path, err);
+                error::report(&err);
+                outcome.errors.push(err);
+            }
+        }
+    }
+
+    Ok(outcome)
+}
+
+// The file as text, or None for a binary file that should be skipped.
+fn read_text(config: &Config, path: &Path) -> Result<Option<String>, MinigrepError> {
+    let bytes = fs::read(path).map_err(|err| MinigrepError::io(path, err))?;
+    if config.binary != BinaryFiles::Text && decode::is_binary(&bytes) {
+        return Ok(None);
+    }
+    String::from_utf8(bytes).map(Some).map_err(|source| MinigrepError::Encoding {
+        path: path.to_path_buf(),
+        source,
+    })
+}
+
+// A unified diff for the output of `replace_lines`. A replace never adds or removes
+// lines of its own, though a replacement containing newlines can turn one old line into
+// several new ones.
+pub fn unified_diff(name: &str, lines: &[(&str, String)]) -> String {
+    let changed: Vec<usize> = (0..lines.len())
+        .filter(|&index| lines[index].0 != lines[index].1)
+        .collect();
+
+    // a/ and b/ prefixes so the diff applies with `patch -p1`, except on absolute paths
+    let (a, b) = if Path::new(name).is_absolute() { ("", "") } else { ("a/", "b/") };
+    let mut diff = format!("--- {a}{name}\n+++ {b}{name}\n");
+    // how many more lines the new side has than the old one, before the current hunk
+    let mut shift: isize = 0;
+    let mut next = 0;
+    while next < changed.len() {
+        // grow the hunk while the next change is close enough for the contexts to touch
+        let mut last = next;
+        while last + 1 < changed.len() && changed[last + 1] - changed[last] <= 2 * DIFF_CONTEXT {
+            last += 1;
+        }
+        let start = changed[next].saturating_sub(DIFF_CONTEXT);
+        let end = (changed[last] + 1 + DIFF_CONTEXT).min(lines.len());
+
+        // like diff -u, a run of changed lines shows all its removals before its additions
+        let mut body = String::new();
+        let mut added = String::new();
+        let mut new_len = 0;
+        for (old, new) in &lines[start..end] {
+            if old == new {
+                body.push_str(&added);
+                added.clear();
+                push_diff_line(&mut body, ' ', old);
+                new_len += 1;
+                continue;
+            }
+            push_diff_line(&mut body, '-', old);
+            for line in new.split_inclusive('\n') {
+                push_diff_line(&mut added, '+', line);
+                new_len += 1;
+            }
+        }
+        body.push_str(&added);
+
+        let old_len = end - start;
+        let new_start = (start as isize + 1 + shift) as usize;
+        diff.push_str(&format!(
+            "@@ -{},{old_len} +{new_start},{new_len} @@\n{body}",
+            start + 1
+        ));
+        shift += new_len as isize - old_len as isize;
+        next = last + 1;
+    }
+
+    diff
+}
+
+fn push_diff_line(body: &mut String, marker: char, line: &str) {
+    body.push(marker);
+    body.push_str(line);
+    if !line.ends_with('\n') {
+        body.push_str("\n\\ No newline at end of file\n");
+    }
+}
+
+fn split_line_ending(line: &str) -> (&str, &str) {
+    let text = line.strip_suffix('\n').unwrap_or(line);
+    let text = text.strip_suffix('\r').unwrap_or(text);
+    (text, &line[text.len()..])
+}
+
+// Writes `contents` to a temporary file next to `path` and renames it over the original,
+// so a crash or a full disk never leaves a half-written file behind.
+pub fn write_atomically(path: &Path, contents: &str) -> io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    let temp = dir.join(format!(".{name}.minigrep-{}", process::id()));
+
+    let result = write_and_rename(&temp, path, contents);
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
+
+fn write_and_rename(temp: &Path, path: &Path, contents: &str) -> io::Result<()> {
+    let permissions = fs::metadata(path)?.permissions();
+    let mut file = File::create(temp)?;
+    file.write_all(contents.as_bytes())?;
+    file.set_permissions(permissions)?;
+    file.sync_all()?;
+    fs::rename(temp, path)
+}
@@ -0,0 +1,551 @@
+use std::collections::{HashSet, VecDeque};
+use std::io::{self, BufRead};
+use std::ops::Range;
+
+use crate::decode::{self, BinaryFiles};
+use crate::error::MinigrepError;
+use crate::field::{Fields, Lookup};
+use crate::matcher::{Boundary, Hit, Matcher};
+
+/// The search itself, without any of the command line around it: what to look for, how,
+/// and how much context to hand over with each match. Whatever it finds goes to a `Sink`,
+/// so the same searcher can print grep style, collect matches or feed another tool.
+///
+/// ```
+/// use std::io;
+///
+/// use minigrep::searcher::{Searcher, SearcherBuilder, Sink, SinkLine};
+///
+/// // keeps the number of every selected line
+/// struct LineNumbers(Vec<usize>);
+///
+/// impl Sink for LineNumbers {
+///     fn matched(&mut self, _searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+///         self.0.push(line.line_number);
+///         Ok(true)
+///     }
+/// }
+///
+/// let searcher = SearcherBuilder::new().pattern("nobody").ignore_case(true).build()?;
+/// let mut found = LineNumbers(Vec::new());
+/// let poem = "I'm nobody! Who are you?\nAre you nobody, too?\nThen there's a pair of us\n";
+/// let summary = searcher.search_reader(poem.as_bytes(), &mut found)?;
+///
+/// assert_eq!(vec![1, 2], found.0);
+/// assert_eq!(2, summary.count);
+/// # Ok::<(), Box<dyn std::error::Error>>(())
+/// ```
+pub struct Searcher {
+    matcher: Matcher,
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    binary: BinaryFiles,
+    records: Records,
+    field: Option<String>,
+    count_matches: bool,
+}
+
+/// Sets up a `Searcher`. Until told otherwise it searches lines for plain text, case
+/// sensitively, without context.
+#[derive(Debug, Default)]
+pub struct SearcherBuilder {
+    patterns: Vec<String>,
+    ignore_case: bool,
+    regex: bool,
+    fuzzy: Option<usize>,
+    boundary: Boundary,
+    invert_match: bool,
+    before_context: usize,
+    after_context: usize,
+    binary: BinaryFiles,
+    records: Records,
+    field: Option<String>,
+    count_matches: bool,
+}
+
+/// What the data is split into before matching: the unit that is selected, counted and
+/// handed to a `Sink`.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub enum Records {
+    /// every line on its own
+    #[default]
+    Lines,
+    /// -U: the whole input is matched at once, so a hit can span line breaks; every line
+    /// a hit touches is selected. The input is read into memory for this.
+    Multiline,
+    /// --paragraph: blocks of lines separated by one or more blank lines
+    Paragraphs,
+    /// --record-separator: whatever comes between two occurrences of this text
+    Separator(String),
+}
+
+/// One line handed to a `Sink`, without its line ending. With Records::Paragraphs or
+/// Records::Separator it's a whole record instead, which can span several lines; the
+/// blank lines or separator around it are left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SinkLine<'a> {
+    /// 1-based, the same way editors and grep -n count; for a record, that of its first line
+    pub line_number: usize,
+    /// byte offset of the start of the line within the data searched
+    pub byte_offset: usize,
+    pub line: &'a str,
+    /// with a field to match (see `SearcherBuilder::field`), where in `line` it is, if it
+    /// appears there as it was matched
+    pub field: Option<Range<usize>>,
+}
+
+/// What searching one file or stream turned up.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FileSummary {
+    /// how many lines were selected
+    pub count: usize,
+    /// whether the data looked binary, so its lines weren't passed on
+    pub binary: bool,
+    /// how many hits there were in the selected lines, if asked for (see
+    /// `SearcherBuilder::count_matches`)
+    pub matches: usize,
+    /// how much data was searched before the search ended
+    pub bytes: u64,
+}
+
+/// Receives the results of a search, one line at a time. Returning Ok(false) ends the
+/// search early, and an error ends it and is passed back to the caller.
+pub trait Sink {
+    /// A selected line: one that matches, or with invert_match one that doesn't.
+    fn matched(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool>;
+
+    /// A line of before/after context around a selected line.
+    fn context(&mut self, _searcher: &Searcher, _line: &SinkLine) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called once, when the data turns out to be binary. From then on selected lines are
+    /// still passed to `matched` to be counted, but no more context lines follow.
+    fn binary(&mut self, _searcher: &Searcher, _line_number: usize) -> io::Result<bool> {
+        Ok(true)
+    }
+
+    /// Called once the search is over, however it ended.
+    fn finish(&mut self, _searcher: &Searcher, _summary: &FileSummary) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// A sink in front of another that holds back the selected lines until the search is
+/// over, then passes them on closest first, for --fuzzy results ranked by distance (see
+/// `Matcher::distance`). Lines that are equally close stay in order. Context lines
+/// would end up next to the wrong lines once they're reordered, so they're dropped.
+pub struct Ranked<S> {
+    inner: S,
+    lines: Vec<HeldLine>,
+}
+
+struct HeldLine {
+    distance: usize,
+    line_number: usize,
+    byte_offset: usize,
+    line: String,
+    field: Option<Range<usize>>,
+}
+
+impl<S: Sink> Ranked<S> {
+    pub fn new(inner: S) -> Ranked<S> {
+        Ranked { inner, lines: Vec::new() }
+    }
+}
+
+impl<S: Sink> Sink for Ranked<S> {
+    fn matched(&mut self, searcher: &Searcher, line: &SinkLine) -> io::Result<bool> {
+        let text = line.field.clone().map_or(line.line, |field| &line.line[field]);
+        let distance = searcher.matcher().distance(text).unwrap_or(0);
+        self.lines.push(HeldLine {
+            distance,
+            line_number: line.line_number,
+            byte_offset: line.byte_offset,
+            line: line.line.to_string(),
+            field: line.field.clone(),
+        });
+        Ok(true)
+    }
+
+    fn binary(&mut self, searcher: &Searcher, line_number: usize) -> io::Result<bool> {
+        self.inner.binary(searcher, line_number)
+    }
+
+    fn finish(&mut self, searcher: &Searcher, summary: &FileSummary) -> io::Result<()> {
+        self.lines.sort_by_key(|held| held.distance);
+        for held in self.lines.drain(..) {
+            let line = SinkLine {
+                line_number: held.line_number,
+                byte_offset: held.byte_offset,
+                line: &held.line,
+                field: held.field,
+            };
+            if !self.inner.matched(searcher, &line)? {
+                break;
+            }
+        }
+        self.inner.finish(searcher, summary)
+    }
+}
+
+impl SearcherBuilder {
+    pub fn new() -> SearcherBuilder {
+        SearcherBuilder::default()
+    }
+
+    /// Adds a pattern to search for; a line is selected if any of them hits.
+    pub fn pattern(mut self, pattern: impl Into<String>) -> SearcherBuilder {
+        self.patterns.push(pattern.into());
+        self
+    }
+
+    pub fn patterns<S: Into<String>>(mut self, patterns: impl IntoIterator<Item = S>) -> SearcherBuilder {
+        self.patterns.extend(patterns.into_iter().map(Into::into));
+        self
+    }
+
+    pub fn ignore_case(mut self, yes: bool) -> SearcherBuilder {
+        self.ignore_case = yes;
+        self
+    }
+
+    /// treat the patterns as regular expressions instead of plain substrings
+    pub fn regex(mut self, yes: bool) -> SearcherBuilder {
+        self.regex = yes;
+        self
+    }
+
+    /// match approximately instead, within this many edits (see `Matcher::fuzzy`)
+    pub fn fuzzy(mut self, max_distance: Option<usize>) -> SearcherBuilder {
+        self.fuzzy = max_distance;
+        self
+    }
+
+    pub fn boundary(mut self, boundary: Boundary) -> SearcherBuilder {
+        self.boundary = boundary;
+        self
+    }
+
+    pub fn invert_match(mut self, yes: bool) -> SearcherBuilder {
+        self.invert_match = yes;
+        self
+    }
+
+    pub fn before_context(mut self, lines: usize) -> SearcherBuilder {
+        self.before_context = lines;
+        self
+    }
+
+    pub fn after_context(mut self, lines: usize) -> SearcherBuilder {
+        self.after_context = lines;
+        self
+    }
+
+    pub fn binary(mut self, binary: BinaryFiles) -> SearcherBuilder {
+        self.binary = binary;
+        self
+    }
+
+    pub fn records(mut self, records: Records) -> SearcherBuilder {
+        self.records = records;
+        self
+    }
+
+    /// Only match against this field of each record, which is read as a JSON object or a
+    /// CSV row (see `field::Fields`); the record is still what's selected. Records without
+    /// the field never match.
+    pub fn field(mut self, name: Option<String>) -> SearcherBuilder {
+        self.field = name;
+        self
+    }
+
+    /// Also count every hit in the selected lines, for `FileSummary::matches`; with
+    /// invert_match, every selected line counts as one.
+    pub fn count_matches(mut self, yes: bool) -> SearcherBuilder {
+        self.count_matches = yes;
+        self
+    }
+
+    /// Fails if a pattern isn't a valid regex.
+    pub fn build(self) -> Result<Searcher, MinigrepError> {
+        let matcher = match self.fuzzy {
+            Some(max_distance) => Matcher::fuzzy(&self.patterns, self.ignore_case, max_distance),
+            None => Matcher::with_patterns(&self.patterns, self.ignore_case, self.regex)?,
+        };
+        let matcher = matcher.bounded(self.boundary);
+        Ok(self.build_with_matcher(matcher))
+    }
+
+    /// Uses `matcher` as it is, instead of building one from the patterns, case mode and
+    /// boundary set on the builder.
+    pub fn build_with_matcher(self, matcher: Matcher) -> Searcher {
+        Searcher {
+            matcher,
+            invert_match: self.invert_match,
+            before_context: self.before_context,
+            after_context: self.after_context,
+            binary: self.binary,
+            records: self.records,
+            field: self.field,
+            count_matches: self.count_matches,
+        }
+    }
+}
+
+impl Searcher {
+    pub fn matcher(&self) -> &Matcher {
+        &self.matcher
+    }
+
+    pub fn invert_match(&self) -> bool {
+        self.invert_match
+    }
+
+    pub fn records(&self) -> &Records {
+        &self.records
+    }
+
+    pub fn field(&self) -> Option<&str> {
+        self.field.as_deref()
+    }
+
+    /// The hits to point out in a selected line: with a field, only those within it, and
+    /// none if the field only matched once its quotes or escapes were undone.
+    pub fn hits(&self, line: &SinkLine) -> Vec<Hit> {
+        match (&self.field, &line.field) {
+            (None, _) => self.matcher.find_all(line.line),
+            (Some(_), None) => Vec::new(),
+            (Some(_), Some(field)) => {
+                let mut hits = self.matcher.find_all(&line.line[field.clone()]);
+                for hit in &mut hits {
+                    hit.span = hit.span.start + field.start..hit.span.end + field.start;
+                }
+                hits
+            }
+        }
+    }
+
+    /// Searches `reader` one line at a time, so memory use only depends on the longest line
+    /// and the amount of before context, never on the size of the input.
+    ///
+    /// Lines are read as bytes and decoded one at a time (see `decode::line`), so no data is
+    /// too broken to search. Data with a NUL byte at the start, or in any line after it, is
+    /// binary: the sink hears about it through `Sink::binary`, and with
+    /// BinaryFiles::WithoutMatch the search ends there as if nothing had matched.
+    ///
+    /// Paragraphs and records are read the same way, one at a time. Multiline mode is the
+    /// exception: it needs all of the data to match against, and only then goes through
+    /// it line by line to hand over the lines that were hit.
+    pub fn search_reader<S: Sink>(&self, mut reader: impl BufRead, sink: &mut S) -> io::Result<FileSummary> {
+        if self.records == Records::Multiline {
+            let mut data = Vec::new();
+            reader.read_to_end(&mut data)?;
+            let hit = self.multiline_hits(&decode::line(&data));
+            return self.search_records(data.as_slice(), sink, |line_number, _| {
+                hit.contains(&line_number) != self.invert_match
+            });
+        }
+        self.search_records(reader, sink, |_, text| {
+            text.is_some_and(|text| self.matcher.is_match(text)) != self.invert_match
+        })
+    }
+
+    // The search loop proper, over whatever `read_record` splits the data into, with
+    // `is_selected` deciding about each record given its line number and the text to
+    // match: the record, or its field (None if it doesn't have one).
+    fn search_records<S: Sink>(
+        &self,
+        mut reader: impl BufRead,
+        sink: &mut S,
+        mut is_selected: impl FnMut(usize, Option<&str>) -> bool,
+    ) -> io::Result<FileSummary> {
+        let check_binary = self.binary != BinaryFiles::Text;
+        let mut summary = FileSummary::default();
+        // the last few non-matching lines, kept around in case a match follows them
+        let mut before: VecDeque<(usize, usize, String)> = VecDeque::new();
+        let mut after_left = 0;
+        let mut fields = self.field.as_deref().map(Fields::new);
+
+        let mut buffer = Vec::new();
+        // how many lines have been read in full so far
+        let mut lines_read = 0;
+        let mut offset = 0;
+        let mut go_on = true;
+        if check_binary && decode::is_binary(reader.fill_buf()?) {
+            summary.binary = true;
+            go_on = self.binary_found(sink, &mut summary, 1)?;
+        }
+
+        while go_on {
+            buffer.clear();
+            let read = self.read_record(&mut reader, &mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            let text = decode::line(&buffer);
+            let (skipped_lines, skipped_bytes, line) = self.record_text(&text);
+            let line_number = lines_read + skipped_lines + 1;
+            let byte_offset = offset + skipped_bytes;
+            lines_read += buffer.iter().filter(|&&byte| byte == b'\n').count();
+            offset += read;
+            summary.bytes += read as u64;
+            // blank lines between paragraphs or records aren't records of their own
+            if line.is_empty() && matches!(self.records, Records::Paragraphs | Records::Separator(_)) {
+                continue;
+            }
+            let (selected, field) = match fields.as_mut().map(|fields| fields.lookup(line)) {
+                None => (is_selected(line_number, Some(line)), None),
+                // a CSV header names the fields rather than being a record
+                Some(Lookup::Header) => continue,
+                Some(Lookup::Found(field)) => (is_selected(line_number, Some(&field.text)), field.span),
+                Some(Lookup::Missing) => (is_selected(line_number, None), None),
+            };
+
+            if check_binary && !summary.binary && decode::is_binary(&buffer) {
+                summary.binary = true;
+                // context from before this point would only end up next to binary data
+                before.clear();
+                after_left = 0;
+                if !self.binary_found(sink, &mut summary, line_number)? {
+                    break;
+                }
+            }
+            let current = SinkLine { line_number, byte_offset, line, field };
+
+            if selected {
+                summary.count += 1;
+                if self.count_matches {
+                    // a hit that spans lines, or that was only found in an unescaped
+                    // field, doesn't show up in the line itself, but it's there
+                    summary.matches += match self.invert_match {
+                        true => 1,
+                        false => self.hits(&current).len().max(1),
+                    };
+                }
+                for (line_number, byte_offset, line) in before.drain(..) {
+                    let context = SinkLine { line_number, byte_offset, line: &line, field: None };
+                    if !sink.context(self, &context)? {
+                        return self.finish(sink, summary);
+                    }
+                }
+                go_on = sink.matched(self, &current)?;
+                after_left = self.after_context;
+            } else if summary.binary {
+                // binary data has no lines worth showing as context
+            } else if after_left > 0 {
+                go_on = sink.context(self, &current)?;
+                after_left -= 1;
+            } else if self.before_context > 0 {
+                if before.len() == self.before_context {
+                    before.pop_front();
+                }
+                before.push_back((line_number, current.byte_offset, line.to_string()));
+            }
+        }
+
+        self.finish(sink, summary)
+    }
+
+    // Reads the next record into `buffer`, along with whatever ends it: a line ending,
+    // the blank line after a paragraph, or the separator. Returns how many bytes that
+    // was, 0 once there's nothing left.
+    fn read_record(&self, reader: &mut impl BufRead, buffer: &mut Vec<u8>) -> io::Result<usize> {
+        let mut read = 0;
+        match &self.records {
+            Records::Lines | Records::Multiline => return reader.read_until(b'\n', buffer),
+            Records::Paragraphs => {
+                let mut has_text = false;
+                loop {
+                    let start = buffer.len();
+                    let line = reader.read_until(b'\n', buffer)?;
+                    let blank = buffer[start..].trim_ascii().is_empty();
+                    read += line;
+                    if line == 0 || (blank && has_text) {
+                        break;
+                    }
+                    has_text |= !blank;
+                }
+            }
+            Records::Separator(separator) => {
+                let separator = separator.as_bytes();
+                let Some(&last) = separator.last() else {
+                    return reader.read_until(b'\n', buffer);
+                };
+                loop {
+                    let part = reader.read_until(last, buffer)?;
+                    read += part;
+                    if part == 0 || buffer.ends_with(separator) {
+                        break;
+                    }
+                }
+            }
+        }
+        Ok(read)
+    }
+
+    // The part of a record that is matched and passed on, after how many lines and bytes
+    // of leading blank lines. A paragraph or record also loses its separator and any
+    // whitespace at its end.
+    fn record_text<'t>(&self, record: &'t str) -> (usize, usize, &'t str) {
+        let record = match &self.records {
+            Records::Lines | Records::Multiline => return (0, 0, trim_line_ending(record)),
+            Records::Paragraphs => record,
+            Records::Separator(separator) => record.strip_suffix(separator.as_str()).unwrap_or(record),
+        };
+
+        let mut skipped_lines = 0;
+        let mut skipped_bytes = 0;
+        for line in record.split_inclusive('\n') {
+            if !line.ends_with('\n') || !line.trim().is_empty() {
+                break;
+            }
+            skipped_lines += 1;
+            skipped_bytes += line.len();
+        }
+        (skipped_lines, skipped_bytes, record[skipped_bytes..].trim_end())
+    }
+
+    // The numbers of all the lines that some hit in `text` touches.
+    fn multiline_hits(&self, text: &str) -> HashSet<usize> {
+        let newlines: Vec<usize> = text.match_indices('\n').map(|(offset, _)| offset).collect();
+        let line_of = |offset: usize| newlines.partition_point(|&newline| newline < offset) + 1;
+
+        let mut lines = HashSet::new();
+        for hit in self.matcher.find_all(text) {
+            // a hit that ends with a line ending doesn't reach into the next line
+            let last = hit.span.end.saturating_sub(1).max(hit.span.start);
+            lines.extend(line_of(hit.span.start)..=line_of(last));
+        }
+        lines
+    }
+
+    pub fn search_str<S: Sink>(&self, contents: &str, sink: &mut S) -> io::Result<FileSummary> {
+        self.search_reader(contents.as_bytes(), sink)
+    }
+
+    // Whether to go on searching binary data.
+    fn binary_found<S: Sink>(
+        &self,
+        sink: &mut S,
+        summary: &mut FileSummary,
+        line_number: usize,
+    ) -> io::Result<bool> {
+        if self.binary == BinaryFiles::WithoutMatch {
+            summary.count = 0;
+            return Ok(false);
+        }
+        sink.binary(self, line_number)
+    }
+
+    fn finish<S: Sink>(&self, sink: &mut S, summary: FileSummary) -> io::Result<FileSummary> {
+        sink.finish(self, &summary)?;
+        Ok(summary)
+    }
+}
+
+pub fn trim_line_ending(line: &str) -> &str {
+    let line = line.strip_suffix('\n').unwrap_or(line);
+    line.strip_suffix('\r').unwrap_or(line)
+}
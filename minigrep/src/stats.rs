@@ -0,0 +1,51 @@
+use std::io::{self, Write};
+use std::time::Duration;
+
+use crate::searcher::FileSummary;
+
+// What --stats reports once a run is over, added up over every file.
+//
+// With -l, -L and -q a file is only searched up to its first match, so the matches
+// and bytes only count that far.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    // files that were read and searched
+    pub files_searched: usize,
+    // files that weren't: ruled out by --index, unreadable, binary with -I, or left
+    // over once -q had its answer
+    pub files_skipped: usize,
+    // files with at least one selected line
+    pub files_matched: usize,
+    pub matched_lines: usize,
+    // hits within the selected lines (with -v, the lines themselves)
+    pub matches: usize,
+    // after decompression and decoding
+    pub bytes_searched: u64,
+    pub elapsed: Duration,
+}
+
+impl Stats {
+    // Counts one file, searched or skipped.
+    pub fn add(&mut self, summary: &FileSummary, skipped: bool) {
+        if skipped {
+            self.files_skipped += 1;
+            return;
+        }
+        self.files_searched += 1;
+        self.files_matched += usize::from(summary.count > 0);
+        self.matched_lines += summary.count;
+        self.matches += summary.matches;
+        self.bytes_searched += summary.bytes;
+    }
+
+    // After the results, set apart from them by a blank line.
+    pub fn print(&self, out: &mut impl Write) -> io::Result<()> {
+        writeln!(out)?;
+        writeln!(out, "{} files searched, {} skipped", self.files_searched, self.files_skipped)?;
+        writeln!(out, "{} files matched", self.files_matched)?;
+        writeln!(out, "{} matched lines", self.matched_lines)?;
+        writeln!(out, "{} matches", self.matches)?;
+        writeln!(out, "{} bytes searched", self.bytes_searched)?;
+        writeln!(out, "{:.6} seconds", self.elapsed.as_secs_f64())
+    }
+}
@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+
+use globset::{Glob, GlobSet, GlobSetBuilder};
+use ignore::WalkBuilder;
+
+use crate::error::{self, MinigrepError};
+use crate::index;
+use crate::parser::Config;
+
+#[derive(Debug, Default)]
+pub struct Walk {
+    pub files: Vec<PathBuf>,
+    // already reported on stderr
+    pub errors: Vec<MinigrepError>,
+}
+
+// Collects every file under `root` that should be searched, in a stable (sorted) order.
+//
+// Hidden files/directories and anything listed in .gitignore/.ignore files are skipped
+// unless the config asks for them. `--exclude` globs prune both files and directories,
+// while `--include` globs (if any were given) only decide which files are kept.
+// Globs are matched against the file name, the same way grep does.
+//
+// A directory that can't be read is reported on stderr and skipped rather than ending
+// the walk; they end up in `Walk::errors`.
+pub fn files(root: &Path, config: &Config) -> Result<Walk, MinigrepError> {
+    let include = build_globs(&config.include)?;
+    let exclude = build_globs(&config.exclude)?;
+
+    let walker = WalkBuilder::new(root)
+        .hidden(!config.hidden)
+        .ignore(!config.no_ignore)
+        .git_ignore(!config.no_ignore)
+        .git_global(!config.no_ignore)
+        .git_exclude(!config.no_ignore)
+        .parents(!config.no_ignore)
+        .sort_by_file_name(|a, b| a.cmp(b))
+        .filter_entry(move |entry| {
+            // never prune the root itself, even if its name happens to match
+            entry.depth() == 0 || !exclude.is_match(entry.file_name())
+        })
+        .build();
+
+    let mut walk = Walk::default();
+    for entry in walker {
+        let entry = match entry {
+            Ok(entry) => entry,
+            Err(err) => {
+                let err = MinigrepError::Walk(err);
+                error::report(&err);
+                walk.errors.push(err);
+                continue;
+            }
+        };
+        let is_file = entry.file_type().is_some_and(|ft| ft.is_file());
+        if !is_file {
+            continue;
+        }
+        if !config.include.is_empty() && !include.is_match(entry.file_name()) {
+            continue;
+        }
+        // the --index file (or a temporary one on its way to replacing it) is ours
+        if entry.file_name().to_string_lossy().starts_with(index::INDEX_FILE) {
+            continue;
+        }
+        walk.files.push(entry.into_path());
+    }
+
+    Ok(walk)
+}
+
+fn build_globs(patterns: &[String]) -> Result<GlobSet, globset::Error> {
+    let mut builder = GlobSetBuilder::new();
+    for pattern in patterns {
+        builder.add(Glob::new(pattern)?);
+    }
+    builder.build()
+}